@@ -0,0 +1,110 @@
+/// A stylesheet or script bundled into the binary, so that previews work
+/// without network access.
+pub struct Asset {
+    pub name: &'static str,
+    pub mime: &'static str,
+    pub content: &'static str,
+    /// Where the same file can be loaded from when `--cdn` is given.
+    pub cdn: &'static str,
+}
+
+pub const MARKDOWN_CSS: Asset = Asset {
+    name: "github-markdown.css",
+    mime: "text/css",
+    content: include_str!("../assets/github-markdown.css"),
+    cdn: "https://cdn.jsdelivr.net/npm/github-markdown-css@5.8.1/github-markdown-light.min.css",
+};
+
+/// The color themes, layered over github-markdown-css. Specific to mdr, so
+/// always served locally.
+pub const THEMES_CSS: Asset = Asset {
+    name: "themes.css",
+    mime: "text/css",
+    content: include_str!("../assets/themes.css"),
+    cdn: "/_assets/themes.css",
+};
+
+/// Draws the diagrams of `mermaid` code blocks. Only loaded by pages that
+/// have some.
+pub const MERMAID_JS: Asset = Asset {
+    name: "mermaid.min.js",
+    mime: "text/javascript",
+    content: include_str!("../assets/mermaid.min.js"),
+    cdn: "https://cdn.jsdelivr.net/npm/mermaid@11.6.0/dist/mermaid.min.js",
+};
+
+const ASSETS: &[&Asset] = &[&MARKDOWN_CSS, &THEMES_CSS, &MERMAID_JS];
+
+/// Look up a bundled asset by file name.
+pub fn get(name: &str) -> Option<&'static Asset> {
+    ASSETS.iter().copied().find(|asset| asset.name == name)
+}
+
+impl Asset {
+    /// The URL pages load this asset from.
+    pub fn url(&self, cdn: bool) -> String {
+        if cdn {
+            self.cdn.to_string()
+        } else {
+            format!("/_assets/{}", self.name)
+        }
+    }
+}
+
+/// The URLs of the assets used by the page templates.
+#[derive(Clone)]
+pub struct AssetUrls {
+    pub markdown_css: String,
+    pub themes_css: String,
+    /// Generated by mdr for the selected highlighting theme, so never on a CDN.
+    pub highlight_css: String,
+    /// The stylesheets given with `--css`.
+    pub custom_css: Vec<String>,
+    pub mermaid_js: String,
+    /// Every stylesheet of the page, to put in the page itself rather than
+    /// linking to them, for pages that are not served by mdr.
+    pub inline_css: Option<String>,
+}
+
+impl AssetUrls {
+    pub fn new(cdn: bool, custom_css: usize) -> Self {
+        AssetUrls {
+            markdown_css: MARKDOWN_CSS.url(cdn),
+            themes_css: THEMES_CSS.url(cdn),
+            highlight_css: "/_assets/highlight.css".to_string(),
+            custom_css: (0..custom_css)
+                .map(|index| format!("/_custom/{index}"))
+                .collect(),
+            mermaid_js: MERMAID_JS.url(cdn),
+            inline_css: None,
+        }
+    }
+
+    /// Assets for a page of a static site, which has them under `_assets/`.
+    /// `root` leads from the page to the root of the site, like `../`.
+    pub fn relative(root: &str, custom_css: usize) -> Self {
+        AssetUrls {
+            markdown_css: format!("{root}_assets/{}", MARKDOWN_CSS.name),
+            themes_css: format!("{root}_assets/{}", THEMES_CSS.name),
+            highlight_css: format!("{root}_assets/highlight.css"),
+            custom_css: (0..custom_css)
+                .map(|index| format!("{root}_assets/custom-{index}.css"))
+                .collect(),
+            mermaid_js: format!("{root}_assets/{}", MERMAID_JS.name),
+            inline_css: None,
+        }
+    }
+
+    /// Assets for a page carrying its stylesheets, and its scripts as data
+    /// URIs.
+    pub fn inline(css: String, mermaid_js: String) -> Self {
+        AssetUrls {
+            markdown_css: String::new(),
+            themes_css: String::new(),
+            highlight_css: String::new(),
+            custom_css: Vec::new(),
+            mermaid_js,
+            inline_css: Some(css),
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use askama::Template;
+use clap::ArgMatches;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+
+use crate::{
+    assets::{AssetUrls, MARKDOWN_CSS, MERMAID_JS, THEMES_CSS},
+    export::{local_path, rewrite_attributes},
+    is_markdown, list_markdown_files,
+    markdown::{self, escape},
+    resolve_asset, theme, IndexTemplate, PageOptions,
+};
+
+/// Characters to percent-encode in the links of the navigation.
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?');
+
+/// `mdr build`: render every markdown file of a directory to a static site,
+/// mirroring the directory structure.
+pub fn run(args: &ArgMatches) -> Result<()> {
+    let dir = Path::new(args.value_of("dir").unwrap());
+    if !dir.is_dir() {
+        return Err(anyhow!("{} is not a directory", dir.display()));
+    }
+    let root = dir.canonicalize()?;
+    let output = PathBuf::from(args.value_of("output").unwrap());
+    // Copying the assets onto themselves would empty them, and the pages could
+    // overwrite files of the directory.
+    fs::create_dir_all(&output)?;
+    if root.starts_with(output.canonicalize()?) {
+        return Err(anyhow!(
+            "the output directory {} must not contain {}",
+            output.display(),
+            dir.display()
+        ));
+    }
+    let options = PageOptions::from_args(args)?;
+
+    let files = list_markdown_files(&root);
+
+    write_stylesheets(&output, &options)?;
+
+    // Images and other files linked from the pages, relative to the root.
+    let mut assets = BTreeSet::new();
+    let mut diagrams = false;
+    for file in &files {
+        let html = page(&root, file, &files, &options, &mut assets, &mut diagrams)?;
+        write(&output.join(html_path(file)), html)?;
+    }
+
+    // The library is big, so only sites with diagrams carry it.
+    if diagrams {
+        write(
+            &output.join("_assets").join(MERMAID_JS.name),
+            MERMAID_JS.content,
+        )?;
+    }
+
+    for asset in &assets {
+        let target = output.join(asset);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(root.join(asset), target)?;
+    }
+
+    if !files.iter().any(|file| html_path(file) == "index.html") {
+        write(
+            &output.join("index.html"),
+            index_page(&root, &files, &options)?,
+        )?;
+    }
+
+    println!("Built {} pages into {}", files.len(), output.display());
+
+    Ok(())
+}
+
+/// The page of the markdown file at `file`, relative to the root, recording
+/// the local files it links to in `assets`, and setting `diagrams` if it has
+/// mermaid diagrams.
+fn page(
+    root: &Path,
+    file: &str,
+    files: &[String],
+    options: &PageOptions,
+    assets: &mut BTreeSet<PathBuf>,
+    diagrams: &mut bool,
+) -> Result<String> {
+    let markdown =
+        fs::read_to_string(root.join(file)).map_err(|e| anyhow!("could not read {file}: {e}"))?;
+    let rendering = markdown::render(&markdown, &options.render);
+    *diagrams |= rendering.has_diagrams();
+    let content = rendering.html();
+
+    let dir = Path::new(file).parent().unwrap_or(Path::new(""));
+    let prefix = "../".repeat(dir.components().count());
+
+    let content = rewrite_attributes(&content, "a", "href", |href| {
+        assets.extend(linked_asset(root, dir, href));
+        site_link(href, &prefix)
+    });
+    let content = rewrite_attributes(&content, "img", "src", |src| {
+        assets.extend(linked_asset(root, dir, src));
+        site_link(src, &prefix)
+    });
+
+    let filename = Path::new(file).file_name().unwrap_or_default();
+    let filename = filename.to_string_lossy();
+    let page = IndexTemplate {
+        filename: filename.to_string(),
+        title: rendering.title(&filename),
+        ip: String::new(),
+        port: String::new(),
+        path: String::new(),
+        content,
+        assets: AssetUrls::relative(&prefix, options.custom_css.len()),
+        theme: options.theme.to_string(),
+        themes: theme::names(),
+        live: false,
+        sidebar: Some(navigation(files, Some(file), &prefix)),
+    };
+
+    Ok(page.render()?)
+}
+
+/// The home page of a site without an `index.md`, listing every page.
+fn index_page(root: &Path, files: &[String], options: &PageOptions) -> Result<String> {
+    let name = root.file_name().unwrap_or_default().to_string_lossy();
+    let content = format!(
+        "<h1>{}</h1>\n{}",
+        escape(&name),
+        navigation(files, None, "")
+    );
+
+    let page = IndexTemplate {
+        filename: name.to_string(),
+        title: name.to_string(),
+        ip: String::new(),
+        port: String::new(),
+        path: String::new(),
+        content,
+        assets: AssetUrls::relative("", options.custom_css.len()),
+        theme: options.theme.to_string(),
+        themes: theme::names(),
+        live: false,
+        sidebar: None,
+    };
+
+    Ok(page.render()?)
+}
+
+/// Nested lists of links to every page, following the directories. `prefix`
+/// leads from the page showing them to the root of the site.
+fn navigation(files: &[String], current: Option<&str>, prefix: &str) -> String {
+    let mut html = String::from("<ul>\n");
+    let mut open_dirs: Vec<&str> = Vec::new();
+
+    // The files are sorted, so the files of a directory come one after another.
+    for file in files {
+        let mut parts: Vec<&str> = file.split('/').collect();
+        let name = parts.pop().unwrap_or_default();
+
+        let common = open_dirs
+            .iter()
+            .zip(&parts)
+            .take_while(|(open, dir)| open == dir)
+            .count();
+        for _ in common..open_dirs.len() {
+            html.push_str("</ul>\n</li>\n");
+        }
+        open_dirs.truncate(common);
+        for dir in &parts[common..] {
+            html.push_str(&format!("<li>{}\n<ul>\n", escape(dir)));
+            open_dirs.push(dir);
+        }
+
+        let href = format!("{prefix}{}", utf8_percent_encode(&html_path(file), PATH));
+        let class = if current == Some(file.as_str()) {
+            " class=\"current\""
+        } else {
+            ""
+        };
+        let title = Path::new(name).file_stem().unwrap_or_default();
+        html.push_str(&format!(
+            "<li><a href=\"{}\"{class}>{}</a></li>\n",
+            escape(&href),
+            escape(&title.to_string_lossy())
+        ));
+    }
+
+    for _ in open_dirs {
+        html.push_str("</ul>\n</li>\n");
+    }
+    html.push_str("</ul>\n");
+
+    html
+}
+
+/// Point a local link at what it becomes in the site: markdown files at their
+/// pages, and paths from the root of the previewed directory relative to the
+/// page, so that the site works from any location.
+fn site_link(href: &str, prefix: &str) -> Option<String> {
+    local_path(href)?;
+
+    let (path, suffix) = href.split_at(href.find(['?', '#']).unwrap_or(href.len()));
+    let path = match path.strip_prefix('/') {
+        Some(path) => format!("{prefix}{path}"),
+        None => path.to_string(),
+    };
+
+    let path = match path.rfind('.') {
+        Some(dot) if is_markdown(Path::new(&path)) => format!("{}.html", &path[..dot]),
+        _ => path,
+    };
+
+    Some(format!("{path}{suffix}"))
+}
+
+/// The local file, other than a markdown file, that a link of the page in
+/// `dir` points to, relative to the root. Files outside of the root are not
+/// published.
+fn linked_asset(root: &Path, dir: &Path, href: &str) -> Option<PathBuf> {
+    let path = local_path(href)?;
+    let path = match path.strip_prefix('/') {
+        Some(path) => PathBuf::from(path),
+        None => dir.join(path),
+    };
+
+    let file_path = resolve_asset(root, &normalize(&path)?.to_string_lossy())?;
+    if is_markdown(&file_path) {
+        return None;
+    }
+
+    file_path.strip_prefix(root).ok().map(Path::to_path_buf)
+}
+
+/// Resolve the `.` and `..` components of a path relative to the root,
+/// without following symlinks. `None` if it leads out of the root.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
+
+/// Where the page of a markdown file goes in the site.
+fn html_path(file: &str) -> String {
+    Path::new(file)
+        .with_extension("html")
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Write the stylesheets shared by every page.
+fn write_stylesheets(output: &Path, options: &PageOptions) -> Result<()> {
+    let dir = output.join("_assets");
+
+    write(&dir.join(MARKDOWN_CSS.name), MARKDOWN_CSS.content)?;
+    write(&dir.join(THEMES_CSS.name), THEMES_CSS.content)?;
+    write(&dir.join("highlight.css"), &options.highlight_css)?;
+    for (index, file) in options.custom_css.iter().enumerate() {
+        write(&dir.join(format!("custom-{index}.css")), fs::read(file)?)?;
+    }
+
+    Ok(())
+}
+
+/// Write a file, creating its directory if needed.
+fn write(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn site_links() {
+        assert_eq!(site_link("x.md", "../").as_deref(), Some("x.html"));
+        assert_eq!(
+            site_link("/abs.md", "../../").as_deref(),
+            Some("../../abs.html")
+        );
+        assert_eq!(site_link("x.md#frag", "").as_deref(), Some("x.html#frag"));
+        assert_eq!(site_link("x.md?q=1", "").as_deref(), Some("x.html?q=1"));
+        assert_eq!(
+            site_link("/img/a.png?a=1&amp;b=2", "../").as_deref(),
+            Some("../img/a.png?a=1&amp;b=2")
+        );
+        assert_eq!(site_link("../up.md", "").as_deref(), Some("../up.html"));
+        assert_eq!(site_link("https://example.com/x.md", ""), None);
+        assert_eq!(site_link("mailto:a@example.com", ""), None);
+        assert_eq!(site_link("//example.com/x.md", ""), None);
+        assert_eq!(site_link("#frag", ""), None);
+    }
+
+    #[test]
+    fn linked_assets() {
+        let dir = std::env::temp_dir().join(format!("mdr-build-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("docs/img")).unwrap();
+        fs::write(root.join("docs/img/a b.png"), "png").unwrap();
+        fs::write(root.join("docs/other.md"), "# Other").unwrap();
+        fs::write(root.join("logo.png"), "png").unwrap();
+        fs::write(dir.join("secret.png"), "png").unwrap();
+        let root = root.canonicalize().unwrap();
+        let docs = Path::new("docs");
+
+        let asset = |href| linked_asset(&root, docs, href);
+        assert_eq!(
+            asset("img/a%20b.png"),
+            Some(PathBuf::from("docs/img/a b.png"))
+        );
+        assert_eq!(
+            asset("img/a%20b.png#x"),
+            Some(PathBuf::from("docs/img/a b.png"))
+        );
+        assert_eq!(
+            asset("/logo.png?v=1&amp;w=2"),
+            Some(PathBuf::from("logo.png"))
+        );
+        assert_eq!(asset("../logo.png"), Some(PathBuf::from("logo.png")));
+        assert_eq!(
+            asset("./img/../../logo.png"),
+            Some(PathBuf::from("logo.png"))
+        );
+        assert_eq!(asset("../../secret.png"), None);
+        assert_eq!(asset("/../secret.png"), None);
+        assert_eq!(asset("other.md"), None);
+        assert_eq!(asset("missing.png"), None);
+        assert_eq!(asset("https://example.com/logo.png"), None);
+        assert_eq!(asset("//example.com/logo.png"), None);
+    }
+
+    #[test]
+    fn navigation_follows_directories() {
+        let files = ["a.md", "docs/b c.md", "docs/deep/d.md", "e.md"].map(String::from);
+        assert_eq!(
+            navigation(&files, Some("docs/b c.md"), "../"),
+            "<ul>\n\
+             <li><a href=\"../a.html\">a</a></li>\n\
+             <li>docs\n<ul>\n\
+             <li><a href=\"../docs/b%20c.html\" class=\"current\">b c</a></li>\n\
+             <li>deep\n<ul>\n\
+             <li><a href=\"../docs/deep/d.html\">d</a></li>\n\
+             </ul>\n</li>\n\
+             </ul>\n</li>\n\
+             <li><a href=\"../e.html\">e</a></li>\n\
+             </ul>\n"
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use askama::Template;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::ArgMatches;
+use percent_encoding::percent_decode_str;
+
+use crate::{
+    assets::{AssetUrls, MARKDOWN_CSS, MERMAID_JS, THEMES_CSS},
+    markdown, theme,
+    watch::{absolute, Change, FileWatcher},
+    IndexTemplate, PageOptions,
+};
+
+/// `mdr export`: write a markdown file to a self-contained HTML file, and with
+/// `--watch`, again whenever one of the files it is made of changes.
+pub async fn run(args: &ArgMatches<'_>) -> Result<()> {
+    let file = Path::new(args.value_of("file").unwrap());
+    let output = match args.value_of("output") {
+        Some(output) => PathBuf::from(output),
+        None => file.with_extension("html"),
+    };
+    let options = PageOptions::from_args(args)?;
+    let embed_images = args.is_present("embed-images");
+
+    let mut sources = Vec::new();
+    export(file, &output, &options, embed_images, &mut sources)?;
+    if !args.is_present("watch") {
+        return Ok(());
+    }
+
+    let mut file = file.to_path_buf();
+    let mut watcher = FileWatcher::new(&[])?;
+    watch_sources(&mut watcher, &sources);
+    println!("Watching {} for changes", file.display());
+
+    loop {
+        for change in watcher.changed().await? {
+            if let Change::Renamed { from, to } = change {
+                if from == absolute(&file)? {
+                    watcher.follow(&from, &to)?;
+                    file = to;
+                }
+            }
+        }
+
+        // Keep watching through errors, such as a stylesheet being removed.
+        sources.clear();
+        if let Err(e) = export(&file, &output, &options, embed_images, &mut sources) {
+            eprintln!("Error: {e}");
+        }
+        watch_sources(&mut watcher, &sources);
+    }
+}
+
+/// Watch the files a page was made of. Those that cannot be watched, like an
+/// image in a directory that does not exist, are reported and skipped.
+fn watch_sources(watcher: &mut FileWatcher, sources: &[PathBuf]) {
+    for source in sources {
+        if let Err(e) = watcher.watch(source) {
+            eprintln!("Error: could not watch {}: {e}", source.display());
+        }
+    }
+}
+
+/// Export a markdown file to `output`, recording the files the page was made
+/// of in `sources`.
+fn export(
+    file: &Path,
+    output: &Path,
+    options: &PageOptions,
+    embed_images: bool,
+    sources: &mut Vec<PathBuf>,
+) -> Result<()> {
+    std::fs::write(output, page(file, options, embed_images, sources)?)?;
+    println!("Exported {} to {}", file.display(), output.display());
+
+    Ok(())
+}
+
+/// The page of a markdown file, carrying its stylesheets and without the live
+/// reload script, so that it can be opened from anywhere. The markdown file,
+/// the stylesheets and the embedded images are recorded in `sources`.
+pub fn page(
+    file: &Path,
+    options: &PageOptions,
+    embed_images: bool,
+    sources: &mut Vec<PathBuf>,
+) -> Result<String> {
+    sources.push(file.to_path_buf());
+    sources.extend(options.custom_css.iter().cloned());
+
+    let markdown = std::fs::read_to_string(file)
+        .map_err(|e| anyhow!("could not read {}: {e}", file.display()))?;
+
+    let rendering = markdown::render(&markdown, &options.render);
+    let mut content = rendering.html();
+    if embed_images {
+        let dir = file.parent().unwrap_or(Path::new(""));
+        content = embed_local_images(&content, dir, sources);
+    }
+
+    // The library is big, so only pages with diagrams carry it.
+    let mermaid_js = if rendering.has_diagrams() {
+        format!(
+            "data:{};base64,{}",
+            MERMAID_JS.mime,
+            STANDARD.encode(MERMAID_JS.content)
+        )
+    } else {
+        String::new()
+    };
+
+    let filename = file.file_name().unwrap_or_default().to_string_lossy();
+    let page = IndexTemplate {
+        filename: filename.to_string(),
+        title: rendering.title(&filename),
+        ip: String::new(),
+        port: String::new(),
+        path: String::new(),
+        content,
+        assets: AssetUrls::inline(stylesheet(options)?, mermaid_js),
+        theme: options.theme.to_string(),
+        themes: theme::names(),
+        live: false,
+        sidebar: None,
+    };
+
+    Ok(page.render()?)
+}
+
+/// Every stylesheet of the page, in the order they are linked when served.
+fn stylesheet(options: &PageOptions) -> Result<String> {
+    let mut css = [
+        MARKDOWN_CSS.content,
+        THEMES_CSS.content,
+        &options.highlight_css,
+    ]
+    .join("\n");
+
+    for file in &options.custom_css {
+        css.push('\n');
+        css.push_str(&std::fs::read_to_string(file)?);
+    }
+
+    Ok(css)
+}
+
+/// Replace the sources of images found next to the markdown file with data
+/// URIs, recording their paths in `sources`. Remote images are left alone.
+fn embed_local_images(html: &str, dir: &Path, sources: &mut Vec<PathBuf>) -> String {
+    rewrite_attributes(html, "img", "src", |src| {
+        let path = dir.join(local_path(src)?.trim_start_matches('/'));
+        let uri = data_uri(&path);
+        sources.push(path);
+        uri
+    })
+}
+
+/// Rewrite the values of an attribute of every `tag` element in generated
+/// HTML. `rewrite` gets the escaped value, and returns the escaped value to
+/// replace it with, if any.
+pub fn rewrite_attributes(
+    html: &str,
+    tag: &str,
+    attr: &str,
+    mut rewrite: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let tag_start = format!("<{tag} ");
+    let attr_start = format!(" {attr}=\"");
+
+    let mut rewritten = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find(&tag_start) {
+        let end = rest[start..]
+            .find('>')
+            .map_or(rest.len(), |end| start + end);
+        let element = &rest[start..end];
+
+        let value = element.find(&attr_start).and_then(|i| {
+            let value_start = i + attr_start.len();
+            element[value_start..]
+                .find('"')
+                .map(|len| (value_start, value_start + len))
+        });
+
+        match value {
+            Some((value_start, value_end)) => {
+                let value = &element[value_start..value_end];
+                rewritten.push_str(&rest[..start + value_start]);
+                rewritten.push_str(&rewrite(value).unwrap_or_else(|| value.to_string()));
+                rewritten.push_str(&element[value_end..]);
+            }
+            None => rewritten.push_str(&rest[..end]),
+        }
+
+        rest = &rest[end..];
+    }
+
+    rewritten.push_str(rest);
+    rewritten
+}
+
+/// The decoded path of a link or image source pointing to a local file,
+/// without its query or fragment. `None` for URLs with a scheme, and for
+/// links within the page.
+pub fn local_path(value: &str) -> Option<String> {
+    let value = value.replace("&amp;", "&");
+    let has_scheme = value
+        .split_once(':')
+        .is_some_and(|(scheme, _)| !scheme.is_empty() && !scheme.contains('/'));
+    if has_scheme || value.starts_with("//") {
+        return None;
+    }
+
+    let path = value.split(['?', '#']).next().unwrap_or_default();
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+
+    if path.is_empty() {
+        None
+    } else {
+        Some(path.into_owned())
+    }
+}
+
+/// The data URI of a local image.
+fn data_uri(file_path: &Path) -> Option<String> {
+    match std::fs::read(file_path) {
+        Ok(bytes) => {
+            let mime = mime_guess::from_path(file_path).first_or_octet_stream();
+            Some(format!("data:{mime};base64,{}", STANDARD.encode(bytes)))
+        }
+        Err(e) => {
+            eprintln!("Error: could not embed {}: {e}", file_path.display());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_paths() {
+        assert_eq!(local_path("img/a.png").as_deref(), Some("img/a.png"));
+        assert_eq!(local_path("/abs.md").as_deref(), Some("/abs.md"));
+        assert_eq!(local_path("x.md#frag").as_deref(), Some("x.md"));
+        assert_eq!(local_path("x.md?q").as_deref(), Some("x.md"));
+        assert_eq!(
+            local_path("a%20b.png?x=1&amp;y=2").as_deref(),
+            Some("a b.png")
+        );
+        assert_eq!(local_path("a&amp;b.png").as_deref(), Some("a&b.png"));
+        assert_eq!(local_path("../up.png").as_deref(), Some("../up.png"));
+        assert_eq!(local_path("dir/a:b.png").as_deref(), Some("dir/a:b.png"));
+    }
+
+    #[test]
+    fn remote_and_page_links_are_not_local() {
+        assert_eq!(local_path("https://example.com/a.png"), None);
+        assert_eq!(local_path("data:image/png;base64,AAAA"), None);
+        assert_eq!(local_path("mailto:a@example.com"), None);
+        assert_eq!(local_path("//example.com/a.png"), None);
+        assert_eq!(local_path("#frag"), None);
+        assert_eq!(local_path("?q"), None);
+        assert_eq!(local_path(""), None);
+    }
+
+    #[test]
+    fn rewrite_attribute_values() {
+        let html = "<p><img src=\"a.png\" alt=\"a\"> <a href=\"a.png\">a</a> \
+                    <img alt=\"b\" src=\"b&amp;c.png\"><img alt=\"none\"></p>";
+        let mut seen = Vec::new();
+        let rewritten = rewrite_attributes(html, "img", "src", |src| {
+            seen.push(src.to_string());
+            (src == "a.png").then(|| "data:x".to_string())
+        });
+        assert_eq!(seen, ["a.png", "b&amp;c.png"]);
+        assert_eq!(
+            rewritten,
+            "<p><img src=\"data:x\" alt=\"a\"> <a href=\"a.png\">a</a> \
+             <img alt=\"b\" src=\"b&amp;c.png\"><img alt=\"none\"></p>"
+        );
+    }
+
+    #[test]
+    fn rewrite_attributes_of_the_right_tag_only() {
+        let html = "<abbr href=\"x\"><a data-href=\"y\" href=\"z\">";
+        let rewritten = rewrite_attributes(html, "a", "href", |href| Some(href.to_uppercase()));
+        assert_eq!(rewritten, "<abbr href=\"x\"><a data-href=\"y\" href=\"Z\">");
+        assert_eq!(
+            rewrite_attributes("<a href=\"x", "a", "href", |_| None),
+            "<a href=\"x"
+        );
+    }
+}
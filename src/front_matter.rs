@@ -0,0 +1,217 @@
+use std::borrow::Cow;
+
+use serde_json::Value;
+
+use crate::markdown::escape;
+
+/// The fields of the front matter of a document.
+pub type Metadata = serde_json::Map<String, Value>;
+
+/// Fields shown first in the metadata card, in this order.
+const CARD_FIELDS: &[&str] = &["title", "author", "date", "tags"];
+
+/// Split the YAML (between `---` lines) or TOML (between `+++` lines) front
+/// matter off the start of a document, as Jekyll and Hugo use. The front
+/// matter is replaced with blank lines in the markdown, so that the lines of
+/// the rest keep their numbers.
+///
+/// Blocks that do not parse to a table of fields are not front matter: a
+/// document can just as well start with a rule.
+pub fn split(markdown: &str) -> (Metadata, Cow<'_, str>) {
+    let none = || (Metadata::new(), Cow::Borrowed(markdown));
+
+    let Some((delimiter, rest)) = ["---", "+++"].iter().find_map(|delimiter| {
+        let rest = markdown.strip_prefix(delimiter)?;
+        let rest = rest
+            .strip_prefix("\r\n")
+            .or_else(|| rest.strip_prefix('\n'))?;
+        Some((*delimiter, rest))
+    }) else {
+        return none();
+    };
+
+    // The closing delimiter, which YAML also allows to be `...`.
+    let mut offset = 0;
+    let mut end = None;
+    for line in rest.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if trimmed == delimiter || (delimiter == "---" && trimmed == "...") {
+            end = Some((offset, offset + line.len()));
+            break;
+        }
+        offset += line.len();
+    }
+    let Some((fields_end, body_start)) = end else {
+        return none();
+    };
+
+    let fields = &rest[..fields_end];
+    let metadata = match delimiter {
+        "---" => parse_yaml(fields),
+        _ => parse_toml(fields),
+    };
+    let Some(metadata) = metadata else {
+        return none();
+    };
+
+    let front_matter = &markdown[..markdown.len() - rest.len() + body_start];
+    let blank_lines = "\n".repeat(front_matter.matches('\n').count());
+    let body = &rest[body_start..];
+
+    (metadata, Cow::Owned(blank_lines + body))
+}
+
+fn parse_yaml(fields: &str) -> Option<Metadata> {
+    match serde_yaml::from_str(fields).ok()? {
+        Value::Object(metadata) => Some(metadata),
+        Value::Null => Some(Metadata::new()),
+        _ => None,
+    }
+}
+
+fn parse_toml(fields: &str) -> Option<Metadata> {
+    let table: toml::Table = fields.parse().ok()?;
+
+    match toml_to_json(toml::Value::Table(table)) {
+        Value::Object(metadata) => Some(metadata),
+        _ => None,
+    }
+}
+
+/// Convert a TOML value to JSON, with dates as strings like in YAML.
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(string) => Value::String(string),
+        toml::Value::Integer(integer) => Value::from(integer),
+        toml::Value::Float(float) => Value::from(float),
+        toml::Value::Boolean(boolean) => Value::Bool(boolean),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(array) => Value::Array(array.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// The title set in the front matter, if any.
+pub fn title(metadata: &Metadata) -> Option<&str> {
+    metadata
+        .get("title")
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|title| !title.is_empty())
+}
+
+/// A card listing the fields of the front matter, shown above the document.
+pub fn card(metadata: &Metadata) -> String {
+    let known = CARD_FIELDS
+        .iter()
+        .filter_map(|name| metadata.get_key_value(*name));
+    let others = metadata
+        .iter()
+        .filter(|(name, _)| !CARD_FIELDS.contains(&name.as_str()));
+
+    let mut html = String::from("<dl class=\"mdr-metadata\" data-source-line=\"1\">\n");
+    for (name, value) in known.chain(others) {
+        html.push_str(&format!(
+            "<div><dt>{}</dt><dd>{}</dd></div>\n",
+            escape(name),
+            card_value(value)
+        ));
+    }
+    html.push_str("</dl>\n");
+
+    html
+}
+
+/// A field of the card: lists, like tags, as one label per item.
+fn card_value(value: &Value) -> String {
+    match value {
+        Value::String(string) => escape(string),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| format!("<span class=\"mdr-tag\">{}</span>", card_value(item)))
+            .collect::<Vec<_>>()
+            .join(" "),
+        value => escape(&value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yaml() {
+        let (metadata, body) = split("---\ntitle: Hello\ntags: [a, b]\n---\n# Body\n");
+        assert_eq!(title(&metadata), Some("Hello"));
+        assert_eq!(metadata["tags"], serde_json::json!(["a", "b"]));
+        assert_eq!(body, "\n\n\n\n# Body\n");
+    }
+
+    #[test]
+    fn yaml_ending_with_dots() {
+        let (metadata, body) = split("---\ntitle: Hello\n...\nBody");
+        assert_eq!(title(&metadata), Some("Hello"));
+        assert_eq!(body, "\n\n\nBody");
+    }
+
+    #[test]
+    fn toml() {
+        let (metadata, body) = split("+++\ntitle = \"Hi\"\ndate = 2024-01-02\n+++\nBody\n");
+        assert_eq!(title(&metadata), Some("Hi"));
+        assert_eq!(metadata["date"], "2024-01-02");
+        assert_eq!(body, "\n\n\n\nBody\n");
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let (metadata, body) = split("---\r\ntitle: Hello\r\n---\r\nBody\r\n");
+        assert_eq!(title(&metadata), Some("Hello"));
+        assert_eq!(body, "\n\n\nBody\r\n");
+    }
+
+    #[test]
+    fn empty_front_matter() {
+        let (metadata, body) = split("---\n---\nBody");
+        assert!(metadata.is_empty());
+        assert_eq!(body, "\n\nBody");
+    }
+
+    #[test]
+    fn line_numbers_are_kept() {
+        let markdown = "---\na: 1\nb:\n  - 2\n  - 3\n---\n\n# Title\n";
+        let (_, body) = split(markdown);
+        assert_eq!(body.lines().count(), markdown.lines().count());
+        assert_eq!(body.lines().nth(7), Some("# Title"));
+    }
+
+    #[test]
+    fn documents_starting_with_a_rule() {
+        for markdown in [
+            "---\n\nSome text\n",
+            "---\njust a rule\n---\n",
+            "--- \ntitle: Hello\n---\n",
+            "+++\nnot toml\n+++\n",
+            "Text\n---\ntitle: Hello\n---\n",
+            "----\ntitle: Hello\n----\n",
+        ] {
+            let (metadata, body) = split(markdown);
+            assert!(metadata.is_empty(), "{markdown:?}");
+            assert!(matches!(body, Cow::Borrowed(_)), "{markdown:?}");
+        }
+    }
+
+    #[test]
+    fn titles() {
+        let (metadata, _) = split("---\ntitle: '  Spaced  '\n---\n");
+        assert_eq!(title(&metadata), Some("Spaced"));
+        let (metadata, _) = split("---\ntitle: ''\n---\n");
+        assert_eq!(title(&metadata), None);
+        let (metadata, _) = split("---\ntitle: 3\n---\n");
+        assert_eq!(title(&metadata), None);
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+use syntect::{
+    highlighting::ThemeSet,
+    html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+use crate::markdown::escape;
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// Layout of the line number gutter, shared by every theme.
+pub const LINE_NUMBERS_CSS: &str = "\
+.hl-code.with-line-numbers {
+ display: flex;
+}
+.hl-code .line-numbers {
+ flex: none;
+ margin-right: 16px;
+ padding-right: 8px;
+ border-right: 1px solid currentColor;
+ opacity: 0.5;
+ text-align: right;
+ user-select: none;
+}
+";
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn themes() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+/// The names of the available highlighting themes.
+pub fn theme_names() -> Vec<&'static str> {
+    themes().themes.keys().map(String::as_str).collect()
+}
+
+/// The token colors of a highlighting theme. Code blocks keep the background
+/// of the page theme.
+pub fn css(theme: &str) -> Result<String> {
+    let theme = themes().themes.get(theme).ok_or_else(|| {
+        anyhow!(
+            "unknown highlight theme '{theme}' (expected one of: {})",
+            theme_names().join(", ")
+        )
+    })?;
+
+    let css = css_for_theme_with_class_style(theme, CLASS_STYLE)?;
+
+    let mut in_code_rule = false;
+    let css = css
+        .lines()
+        .filter(|line| {
+            if line.starts_with(".hl-code {") {
+                in_code_rule = true;
+            } else if line.starts_with('}') {
+                in_code_rule = false;
+            }
+            !(in_code_rule && line.trim_start().starts_with("background-color"))
+        })
+        .map(|line| format!("{line}\n"))
+        .collect();
+
+    Ok(css)
+}
+
+/// Highlight a code block, picking the syntax from `lang`, the first word of
+/// its info string. Unknown languages are rendered as plain text.
+pub fn highlight(code: &str, lang: &str, line_numbers: bool) -> String {
+    let code_html = highlight_lines(code, lang).unwrap_or_else(|| escape(code));
+
+    let class = if lang.is_empty() {
+        String::new()
+    } else {
+        format!(" class=\"language-{}\"", escape(lang))
+    };
+
+    if line_numbers {
+        let numbers: String = (1..=code.lines().count())
+            .map(|n| format!("{n}\n"))
+            .collect();
+        format!(
+            "<pre class=\"hl-code with-line-numbers\"><span class=\"line-numbers\" aria-hidden=\"true\">{numbers}</span><code{class}>{code_html}</code></pre>\n"
+        )
+    } else {
+        format!("<pre class=\"hl-code\"><code{class}>{code_html}</code></pre>\n")
+    }
+}
+
+fn highlight_lines(code: &str, lang: &str) -> Option<String> {
+    let syntaxes = syntaxes();
+    let syntax = syntaxes.find_syntax_by_token(lang)?;
+
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        generator
+            .parse_html_for_line_which_includes_newline(line)
+            .ok()?;
+    }
+
+    Some(generator.finalize())
+}
@@ -1,123 +1,1181 @@
-use std::fs::{metadata, File};
-use std::io::prelude::*;
-use std::net::SocketAddr;
-use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::read_dir;
+use std::io::{self, IsTerminal};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{anyhow, Result};
 use askama::Template;
 use axum::{
     extract::{
         ws::{Message, WebSocket},
-        Extension, WebSocketUpgrade,
+        ConnectInfo, Extension, Path as RoutePath, Query, WebSocketUpgrade,
     },
-    http::StatusCode,
-    response::{Html, IntoResponse, Response},
-    routing::get,
+    http::{header, HeaderMap, Request, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, put},
     Router,
 };
-use clap::{crate_name, crate_version, App, Arg, ArgMatches};
+use clap::{crate_name, crate_version, App, Arg, ArgMatches, SubCommand};
+use minijinja::Value;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
 use tokio::{
-    sync::watch::{channel, Receiver, Sender},
+    io::AsyncReadExt,
+    sync::{
+        broadcast::{self, error::RecvError},
+        watch::{channel, Receiver, Sender},
+    },
     task,
-    time::{interval, Duration},
+    time::{interval, timeout_at, Duration, Instant},
 };
 
-const INTERVAL_WATCH_MSEC: u64 = 100;
+mod assets;
+mod build;
+mod export;
+mod front_matter;
+mod highlight;
+mod markdown;
+mod math;
+mod patch;
+mod renderers;
+mod theme;
+mod watch;
+
+use assets::AssetUrls;
+use front_matter::Metadata;
+use markdown::{Extensions, RenderOptions, Rendering, EXTENSION_NAMES};
+use patch::Update;
+use renderers::Renderer;
+use watch::{Change, FileWatcher};
+
+const HEARTBEAT_INTERVAL_SEC: u64 = 15;
+
+/// The file argument standing for stdin.
+const STDIN_PATH: &str = "-";
+
+/// How often markdown streamed from stdin is rendered again, at most.
+const STDIN_THROTTLE_MSEC: u64 = 100;
+
+/// How many cursor and click positions can wait for a slow client. Only the
+/// latest one matters, so older ones are dropped past that.
+const POSITIONS_CAPACITY: usize = 16;
+
+const MARKDOWN_EXTENSIONS: &[&str] = &["md", "markdown", "mdown", "mkd", "mkdn"];
 
 #[derive(Clone)]
 struct Config {
     filename: String,
     ip: String,
     port: String,
+    /// Where the pages are served from, like `http://127.0.0.1:8080`.
+    origin: String,
+    root: PathBuf,
+    /// The markdown file served at `/`, relative to `root`, or `None` when
+    /// serving a whole directory.
+    file: Option<PathBuf>,
+    assets: AssetUrls,
+    highlight_css: String,
+    theme: String,
+    /// Stylesheets given with `--css`, served in order under `/_custom/`.
+    custom_css: Vec<PathBuf>,
+    /// A template given with `--template`, used instead of the built-in page.
+    template: Option<PathBuf>,
+}
+
+/// Tells open pages to pick up changes to the custom stylesheets or template.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Reload {
+    Stylesheets,
+    Page,
+}
+
+/// A rendered document, shared by the pages showing it.
+type Rendered = Arc<Rendering>;
+
+/// A source line reported by an editor or the page, to keep them scrolled to
+/// the same place. Sent as JSON over the websockets.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum Position {
+    /// The editor cursor moved to this line: the page scrolls to it.
+    Cursor { line: usize },
+    /// A block starting on this line was clicked in the page: the editor can
+    /// jump to it.
+    Click { line: usize },
+}
+
+/// Messages editor plugins send over their websocket.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum EditorMessage {
+    /// The cursor moved to this line.
+    Cursor { line: usize },
+    /// The unsaved content of the file, to preview instead of what is on disk.
+    Buffer { text: String },
+}
+
+/// A markdown file open in the browser.
+#[derive(Clone)]
+struct Document {
+    rendering: Receiver<Rendered>,
+    positions: broadcast::Sender<Position>,
+    /// The content pushed by an editor, if any, which replaces the file.
+    buffer: Sender<Option<String>>,
+}
+
+impl Document {
+    /// A document showing the renderings sent on `rendering`, along with the
+    /// receiving end of its editor buffer.
+    fn new(rendering: Receiver<Rendered>) -> (Self, Receiver<Option<String>>) {
+        let (positions, _) = broadcast::channel(POSITIONS_CAPACITY);
+        let (buffer, buffer_rx) = channel(None);
+        let document = Document {
+            rendering,
+            positions,
+            buffer,
+        };
+        (document, buffer_rx)
+    }
+}
+
+/// The live renderings of the markdown files opened in the browser, keyed by
+/// path. A file gets its own watcher the first time it is requested, and keeps
+/// it even if the file goes missing so that it can be picked up again.
+#[derive(Clone)]
+struct Documents {
+    root: PathBuf,
+    options: RenderOptions,
+    follow_renames: bool,
+    channels: Arc<Mutex<HashMap<PathBuf, Document>>>,
+}
+
+impl Documents {
+    fn new(root: &Path, options: RenderOptions, follow_renames: bool) -> Result<Self> {
+        Ok(Documents {
+            root: root.canonicalize()?,
+            options,
+            follow_renames,
+            channels: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Serve a document that is not a file of the root directory, like the
+    /// input read from stdin, at `path`.
+    fn insert(&self, path: &str, document: Document) {
+        let key = self.root.join(path);
+        self.channels.lock().unwrap().insert(key, document);
+    }
+
+    /// Get the live rendering of the markdown file at `path`, relative to the
+    /// root directory.
+    async fn subscribe(&self, path: &str) -> Option<Document> {
+        let key = self.root.join(relative_path(path)?);
+
+        if let Some(document) = self.channels.lock().unwrap().get(&key) {
+            return Some(document.clone());
+        }
+
+        let file_path = resolve_asset(&self.root, path).filter(|path| is_markdown(path))?;
+
+        let watcher = match FileWatcher::new(std::slice::from_ref(&file_path)) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                eprintln!("Error: could not watch file: {e}");
+                return None;
+            }
+        };
+
+        // The first rendering is done right away so that pages can be served
+        // with their content already in place. The lock is not held meanwhile,
+        // so that a slow rendering does not hold up the other documents.
+        let (rendering, last) =
+            render_document(file_path.clone(), self.options.clone(), Rendered::default()).await;
+
+        let mut channels = self.channels.lock().unwrap();
+        // Opened by another request while rendering.
+        if let Some(document) = channels.get(&key) {
+            return Some(document.clone());
+        }
+
+        let (chan_tx, chan_rx) = channel(rendering);
+        let (document, buffer_rx) = Document::new(chan_rx);
+        channels.insert(key, document.clone());
+        drop(channels);
+
+        let options = self.options.clone();
+        let follow_renames = self.follow_renames;
+        task::spawn(async move {
+            if let Err(e) = check_file(
+                file_path,
+                watcher,
+                last,
+                &options,
+                follow_renames,
+                buffer_rx,
+                &chan_tx,
+            )
+            .await
+            {
+                eprintln!("Error: stopped watching file: {e}");
+            }
+        });
+
+        Some(document)
+    }
 }
 
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate {
     filename: String,
+    /// The title of the page, from the front matter or else the file name.
+    title: String,
+    ip: String,
+    port: String,
+    path: String,
+    content: String,
+    assets: AssetUrls,
+    theme: String,
+    themes: Vec<&'static str>,
+    /// Keep the page up to date over a websocket. Exported pages are not.
+    live: bool,
+    /// The navigation of a static site, shown next to the content.
+    sidebar: Option<String>,
+}
+
+/// The part of the page head shared by every page, also handed to custom
+/// templates as `head`.
+#[derive(Template)]
+#[template(path = "head.html")]
+struct HeadTemplate {
+    title: String,
+    assets: AssetUrls,
+    theme: String,
+}
+
+/// The toolbar and live reload script, handed to custom templates as
+/// `live_reload`.
+#[derive(Template)]
+#[template(path = "live.html")]
+struct LiveTemplate {
+    assets: AssetUrls,
     ip: String,
     port: String,
+    path: String,
+    theme: String,
+    themes: Vec<&'static str>,
+}
+
+#[derive(Template)]
+#[template(path = "directory.html")]
+struct DirectoryTemplate {
+    filename: String,
+    title: String,
+    files: Vec<String>,
+    assets: AssetUrls,
+    theme: String,
+    themes: Vec<&'static str>,
+}
+
+/// Render an askama template, or an error page if that fails.
+fn render_template(template: &impl Template) -> Response {
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Error: could not render template: {e}"),
+        )
+            .into_response(),
+    }
 }
 
 impl IntoResponse for IndexTemplate {
     fn into_response(self) -> Response {
-        match self.render() {
+        render_template(&self)
+    }
+}
+
+impl IntoResponse for DirectoryTemplate {
+    fn into_response(self) -> Response {
+        render_template(&self)
+    }
+}
+
+#[derive(Deserialize)]
+struct DocumentParams {
+    path: Option<String>,
+}
+
+impl DocumentParams {
+    /// The requested document, defaulting to the file served at `/`.
+    fn path(&self, config: &Config) -> Option<String> {
+        match (&self.path, &config.file) {
+            (Some(path), _) => Some(path.to_string()),
+            (None, Some(file)) => Some(file.to_string_lossy().to_string()),
+            (None, None) => None,
+        }
+    }
+
+    /// The name the page of the requested document is titled after, when its
+    /// front matter has no title.
+    fn filename(&self, config: &Config) -> String {
+        match &self.path {
+            Some(path) => path.to_string(),
+            None => config.filename.to_string(),
+        }
+    }
+
+    /// The live rendering of the requested document.
+    async fn document(&self, config: &Config, documents: &Documents) -> Option<Document> {
+        documents.subscribe(&self.path(config)?).await
+    }
+}
+
+/// The live preview page of a markdown file, with its current rendering.
+async fn page(config: &Config, documents: &Documents, filename: &str, path: &str) -> Response {
+    let document = match documents.subscribe(path).await {
+        Some(document) => document,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let rendering = document.rendering.borrow().clone();
+
+    let page = IndexTemplate {
+        filename: filename.to_string(),
+        title: rendering.title(filename),
+        ip: config.ip.to_string(),
+        port: config.port.to_string(),
+        path: path.to_string(),
+        content: rendering.html(),
+        assets: config.assets.clone(),
+        theme: config.theme.to_string(),
+        themes: theme::names(),
+        live: true,
+        sidebar: None,
+    };
+
+    match &config.template {
+        Some(template) => match render_custom_template(template, page, &rendering.metadata) {
             Ok(html) => Html(html).into_response(),
             Err(e) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 format!("Error: could not render template: {e}"),
             )
                 .into_response(),
+        },
+        None => page.into_response(),
+    }
+}
+
+/// Render a page with a template given on the command line. It is read again
+/// on every request, so edits show up on reload.
+fn render_custom_template(
+    template: &Path,
+    page: IndexTemplate,
+    metadata: &Metadata,
+) -> Result<String> {
+    let source = std::fs::read_to_string(template)?;
+
+    let head = HeadTemplate {
+        title: page.title.to_string(),
+        assets: page.assets.clone(),
+        theme: page.theme.to_string(),
+    }
+    .render()?;
+    let live_reload = LiveTemplate {
+        assets: page.assets,
+        ip: page.ip,
+        port: page.port,
+        path: page.path.to_string(),
+        theme: page.theme.to_string(),
+        themes: page.themes,
+    }
+    .render()?;
+
+    // Named after the file so that `.html` templates get HTML escaping.
+    let name = template.file_name().unwrap_or_default().to_string_lossy();
+    let mut env = minijinja::Environment::new();
+    env.add_template(&name, &source)?;
+
+    let html = env.get_template(&name)?.render(minijinja::context! {
+        filename => page.filename,
+        title => page.title,
+        path => page.path,
+        content => Value::from_safe_string(page.content),
+        metadata => Value::from_serialize(metadata),
+        theme => page.theme,
+        head => Value::from_safe_string(head),
+        live_reload => Value::from_safe_string(live_reload),
+    })?;
+
+    Ok(html)
+}
+
+async fn index_route(config: Extension<Config>, documents: Extension<Documents>) -> Response {
+    match &config.file {
+        Some(file) => {
+            page(
+                &config,
+                &documents,
+                &config.filename,
+                &file.to_string_lossy(),
+            )
+            .await
+        }
+        None => DirectoryTemplate {
+            filename: config.filename.to_string(),
+            title: config.filename.to_string(),
+            files: list_markdown_files(&config.root),
+            assets: config.assets.clone(),
+            theme: config.theme.to_string(),
+            themes: theme::names(),
         }
+        .into_response(),
     }
 }
 
-async fn index_route(config: Extension<Config>) -> impl IntoResponse {
-    IndexTemplate {
-        filename: config.filename.to_string(),
-        ip: config.ip.to_string(),
-        port: config.port.to_string(),
+async fn view_route(
+    RoutePath(path): RoutePath<String>,
+    config: Extension<Config>,
+    documents: Extension<Documents>,
+) -> Response {
+    let path = path.trim_start_matches('/');
+
+    if is_markdown(Path::new(path)) {
+        return page(&config, &documents, path, path).await;
+    }
+
+    match resolve_asset(&config.root, path) {
+        Some(file_path) => serve_file(&file_path).await,
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn content_route(
+    Query(params): Query<DocumentParams>,
+    config: Extension<Config>,
+    documents: Extension<Documents>,
+) -> Response {
+    match params.document(&config, &documents).await {
+        Some(document) => Html(document.rendering.borrow().html()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn bundled_asset_route(RoutePath(name): RoutePath<String>) -> Response {
+    match assets::get(name.trim_start_matches('/')) {
+        Some(asset) => ([(header::CONTENT_TYPE, asset.mime)], asset.content).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn highlight_css_route(config: Extension<Config>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/css")],
+        config.highlight_css.clone(),
+    )
+        .into_response()
+}
+
+async fn custom_css_route(
+    RoutePath(index): RoutePath<usize>,
+    config: Extension<Config>,
+) -> Response {
+    let css = match config.custom_css.get(index) {
+        Some(file_path) => tokio::fs::read_to_string(file_path).await,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    match css {
+        Ok(css) => ([(header::CONTENT_TYPE, "text/css")], css).into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn asset_route(uri: Uri, config: Extension<Config>) -> Response {
+    let path = match decode_path(uri.path()) {
+        Some(path) => path,
+        None => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    let file_path = match resolve_asset(&config.root, &path) {
+        Some(file_path) => file_path,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    // Relative links between markdown files open in the live preview.
+    if is_markdown(&file_path) {
+        return Redirect::to(&format!("/view{}", uri.path())).into_response();
+    }
+
+    serve_file(&file_path).await
+}
+
+async fn serve_file(file_path: &Path) -> Response {
+    match tokio::fs::read(file_path).await {
+        Ok(bytes) => {
+            let mime = mime_guess::from_path(file_path).first_or_octet_stream();
+            ([(header::CONTENT_TYPE, mime.to_string())], bytes).into_response()
+        }
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// The percent-decoded path of a request URI.
+fn decode_path(path: &str) -> Option<String> {
+    percent_decode_str(path)
+        .decode_utf8()
+        .ok()
+        .map(|path| path.into_owned())
+}
+
+/// Whether a file or directory is hidden. Hidden files are never served: the
+/// root can be a home directory, with `.ssh` in it.
+fn is_hidden(name: &OsStr) -> bool {
+    name.to_string_lossy().starts_with('.')
+}
+
+/// Turn a request path into a path relative to the root directory, refusing
+/// anything that is not a plain descendant of it, or that is hidden.
+fn relative_path(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path.trim_start_matches('/'));
+
+    path.components()
+        .all(|component| match component {
+            Component::Normal(name) => !is_hidden(name),
+            Component::CurDir => true,
+            _ => false,
+        })
+        .then(|| path.to_path_buf())
+}
+
+/// Resolve a request path against the root directory, refusing anything that
+/// would escape it (`..` components, symlinks pointing outside, ...) or that
+/// is hidden.
+fn resolve_asset(root: &Path, path: &str) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+    let file_path = root.join(relative_path(path)?).canonicalize().ok()?;
+
+    // Symlinks can lead outside of the root, or to hidden files.
+    let inside = file_path.strip_prefix(&root).ok()?;
+    let hidden = inside
+        .components()
+        .any(|component| matches!(component, Component::Normal(name) if is_hidden(name)));
+
+    if !hidden && file_path.is_file() {
+        Some(file_path)
+    } else {
+        None
+    }
+}
+
+fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase())
+        .is_some_and(|extension| MARKDOWN_EXTENSIONS.contains(&extension.as_str()))
+}
+
+/// List the markdown files below `root` as sorted, `/`-separated relative
+/// paths, skipping hidden files and directories.
+fn list_markdown_files(root: &Path) -> Vec<String> {
+    fn walk(dir: &Path, prefix: &str, files: &mut Vec<String>) {
+        let Ok(entries) = read_dir(dir) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                continue;
+            }
+
+            let path = entry.path();
+            let relative = format!("{prefix}{name}");
+
+            if path.is_dir() {
+                walk(&path, &format!("{relative}/"), files);
+            } else if is_markdown(&path) {
+                files.push(relative);
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    walk(root, "", &mut files);
+    files.sort();
+    files
+}
+
+/// Whether a request may read the live renderings or change what is
+/// previewed. Browsers send the origin of the page making the request, which
+/// has to be one of ours: otherwise any site could read the files or push
+/// markup running scripts in the preview. Editor plugins send none, and are
+/// only trusted from this machine.
+fn is_allowed(headers: &HeaderMap, peer: SocketAddr, config: &Config) -> bool {
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return peer.ip().is_loopback();
+    };
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+
+    origin
+        .strip_prefix("http://")
+        .is_some_and(|host| is_served_host(host, config))
+}
+
+/// Whether a `Host` header, or the host of an origin, names this server: the
+/// address it is bound to, or the local host, on its port. Anything else is a
+/// domain name resolving to this machine, which would let other sites read
+/// the files by rebinding their own name to it.
+fn is_served_host(host: &str, config: &Config) -> bool {
+    if host == config.origin.trim_start_matches("http://") {
+        return true;
+    }
+    let Some(name) = host.strip_suffix(&format!(":{}", config.port)) else {
+        return false;
+    };
+
+    // When bound to every interface, the server is reached through any of
+    // the addresses of the machine.
+    let unspecified = config
+        .ip
+        .parse::<IpAddr>()
+        .is_ok_and(|ip| ip.is_unspecified());
+    let is_address = name
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .is_ok();
+
+    ["localhost", "127.0.0.1", "[::1]"].contains(&name) || (unspecified && is_address)
+}
+
+/// Refuse requests for another host than this server, see [`is_served_host`],
+/// and requests made by pages of other web sites. Following a link or typing
+/// an address sends no origin, and is left to the routes to judge.
+async fn check_request<B>(
+    config: Extension<Config>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let headers = request.headers();
+    let value = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+    let host_allowed = value(header::HOST).is_some_and(|host| is_served_host(host, &config));
+    let origin_allowed = value(header::ORIGIN).is_none_or(|origin| {
+        origin
+            .strip_prefix("http://")
+            .is_some_and(|host| is_served_host(host, &config))
+    });
+
+    if host_allowed && origin_allowed {
+        next.run(request).await
+    } else {
+        StatusCode::FORBIDDEN.into_response()
     }
 }
 
 async fn websocket_route(
     ws: WebSocketUpgrade,
-    chan_rx: Extension<Receiver<String>>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |ws| handle_websocket(ws, chan_rx))
+    Query(params): Query<DocumentParams>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    config: Extension<Config>,
+    documents: Extension<Documents>,
+    reload_rx: Extension<Receiver<Option<Reload>>>,
+) -> Response {
+    if !is_allowed(&headers, peer, &config) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let document = match params.document(&config, &documents).await {
+        Some(document) => document,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let reload_rx = reload_rx.0.clone();
+    let filename = params.filename(&config);
+
+    ws.on_upgrade(move |ws| handle_websocket(ws, document, filename, reload_rx))
+}
+
+async fn handle_websocket(
+    mut ws: WebSocket,
+    document: Document,
+    filename: String,
+    mut reload_rx: Receiver<Option<Reload>>,
+) {
+    let mut chan_rx = document.rendering;
+    let mut positions = document.positions.subscribe();
+
+    // Clients that connect late or reconnect get the current rendering first.
+    // After that they only get the blocks that changed since the rendering
+    // they have, or the whole document again when they ask for a `refresh`.
+    let mut sent = chan_rx.borrow_and_update().clone();
+    reload_rx.borrow_and_update();
+    let message = Update::Full {
+        blocks: &sent.blocks,
+    }
+    .to_json();
+    if let Err(e) = ws.send(Message::Text(message)).await {
+        eprintln!("Error: could not send text message to websocket: {e}");
+        return;
+    }
+
+    // The page may have been loaded before the title last changed.
+    let mut title = sent.title(&filename);
+    let message = Update::Title { title: &title }.to_json();
+    if ws.send(Message::Text(message)).await.is_err() {
+        return;
+    }
+
+    // Ping the client regularly and drop it if it stays silent for a whole
+    // interval. The page sends its own `ping` messages, answered with `pong`,
+    // to notice when the server goes away.
+    let mut heartbeat = interval(Duration::from_secs(HEARTBEAT_INTERVAL_SEC));
+    let mut alive = true;
+
+    // Without custom stylesheets or template nothing ever triggers a reload.
+    let mut reload_open = true;
+
+    loop {
+        tokio::select! {
+            changed = chan_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+
+                let rendering = chan_rx.borrow().clone();
+                if let Some(message) = patch::update(&sent.blocks, &rendering.blocks) {
+                    if let Err(e) = ws.send(Message::Text(message)).await {
+                        eprintln!("Error: could not send text message to websocket: {e}");
+                        break;
+                    }
+                }
+
+                // The title can change without the blocks, when the front
+                // matter is not shown.
+                let new_title = rendering.title(&filename);
+                if new_title != title {
+                    let message = Update::Title { title: &new_title }.to_json();
+                    if ws.send(Message::Text(message)).await.is_err() {
+                        break;
+                    }
+                    title = new_title;
+                }
+                sent = rendering;
+            }
+            position = positions.recv() => match position {
+                Ok(position @ Position::Cursor { .. }) => {
+                    let message = serde_json::to_string(&position).unwrap_or_default();
+                    if ws.send(Message::Text(message)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(Position::Click { .. }) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            },
+            changed = reload_rx.changed(), if reload_open => {
+                if changed.is_err() {
+                    reload_open = false;
+                    continue;
+                }
+
+                let message = match *reload_rx.borrow() {
+                    Some(Reload::Stylesheets) => Update::ReloadCss,
+                    Some(Reload::Page) => Update::Reload,
+                    None => continue,
+                };
+                if ws.send(Message::Text(message.to_json())).await.is_err() {
+                    break;
+                }
+            }
+            _ = heartbeat.tick() => {
+                if !alive || ws.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+                alive = false;
+            }
+            message = ws.recv() => match message {
+                Some(Ok(Message::Text(text))) if text == "ping" => {
+                    alive = true;
+                    if ws.send(Message::Text("pong".to_string())).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Text(text))) if text == "refresh" => {
+                    alive = true;
+                    sent = chan_rx.borrow().clone();
+                    let message = Update::Full { blocks: &sent.blocks }.to_json();
+                    if ws.send(Message::Text(message)).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Text(text))) => {
+                    alive = true;
+                    if let Ok(position @ Position::Click { .. }) = serde_json::from_str(&text) {
+                        let _ = document.positions.send(position);
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => alive = true,
+            },
+        }
+    }
+}
+
+async fn editor_route(
+    ws: WebSocketUpgrade,
+    Query(params): Query<DocumentParams>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    config: Extension<Config>,
+    documents: Extension<Documents>,
+) -> Response {
+    if !is_allowed(&headers, peer, &config) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let document = match params.document(&config, &documents).await {
+        Some(document) => document,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    ws.on_upgrade(move |ws| handle_editor(ws, document))
+}
+
+/// Talk to an editor plugin: it reports its cursor line so that pages follow
+/// it, and is told which line was clicked in a page. It can also send its
+/// unsaved buffer, which is previewed until it disconnects.
+async fn handle_editor(mut ws: WebSocket, document: Document) {
+    let mut positions = document.positions.subscribe();
+    let mut sent_buffer = false;
+
+    loop {
+        tokio::select! {
+            position = positions.recv() => match position {
+                Ok(position @ Position::Click { .. }) => {
+                    let message = serde_json::to_string(&position).unwrap_or_default();
+                    if ws.send(Message::Text(message)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(Position::Cursor { .. }) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            },
+            message = ws.recv() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(EditorMessage::Cursor { line }) => {
+                        let _ = document.positions.send(Position::Cursor { line });
+                    }
+                    Ok(EditorMessage::Buffer { text }) => {
+                        sent_buffer = true;
+                        document.buffer.send_replace(Some(text));
+                    }
+                    Err(e) => eprintln!("Error: unexpected message from editor: {e}"),
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    // Back to the file on disk.
+    if sent_buffer {
+        document.buffer.send_replace(None);
+    }
+}
+
+/// Preview unsaved content sent by an editor instead of the file.
+async fn put_buffer_route(
+    Query(params): Query<DocumentParams>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    config: Extension<Config>,
+    documents: Extension<Documents>,
+    text: String,
+) -> Response {
+    if !is_allowed(&headers, peer, &config) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match params.document(&config, &documents).await {
+        Some(document) => {
+            document.buffer.send_replace(Some(text));
+            StatusCode::NO_CONTENT.into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
 }
 
-async fn handle_websocket(mut ws: WebSocket, mut chan_rx: Extension<Receiver<String>>) {
-    while chan_rx.changed().await.is_ok() {
-        let html = chan_rx.borrow().clone();
+/// Go back to previewing the file on disk.
+async fn delete_buffer_route(
+    Query(params): Query<DocumentParams>,
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    config: Extension<Config>,
+    documents: Extension<Documents>,
+) -> Response {
+    if !is_allowed(&headers, peer, &config) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
 
-        if let Err(e) = ws.send(Message::Text(html)).await {
-            eprintln!("Error: could not send text message to websocket: {e}");
+    match params.document(&config, &documents).await {
+        Some(document) => {
+            document.buffer.send_replace(None);
+            StatusCode::NO_CONTENT.into_response()
         }
+        None => StatusCode::NOT_FOUND.into_response(),
     }
 }
 
-async fn render_markdown(file_path: &PathBuf, chan_tx: &Sender<String>) -> Result<()> {
-    let mut file = File::open(file_path)?;
-    let mut markdown = String::new();
-    file.read_to_string(&mut markdown)?;
+fn render_markdown(markdown: &str, options: &RenderOptions) -> Rendered {
+    markdown::render(markdown, options).into()
+}
 
-    let parser = pulldown_cmark::Parser::new(&markdown);
-    let mut html = String::new();
-    pulldown_cmark::html::push_html(&mut html, parser);
+/// Render markdown on a blocking thread, as external renderers can take a
+/// while.
+async fn render_blocking(markdown: String, options: RenderOptions) -> Rendered {
+    task::spawn_blocking(move || render_markdown(&markdown, &options))
+        .await
+        .unwrap_or_default()
+}
 
-    chan_tx.send(html)?;
+/// Render the file on a blocking thread, along with the new last successful
+/// rendering.
+async fn render_document(
+    file_path: PathBuf,
+    options: RenderOptions,
+    mut last: Rendered,
+) -> (Rendered, Rendered) {
+    let fallback = last.clone();
+    task::spawn_blocking(move || {
+        let rendering = read_and_render(&file_path, &options, &mut last);
+        (rendering, last)
+    })
+    .await
+    .unwrap_or_else(|_| (fallback.clone(), fallback))
+}
 
-    Ok(())
+/// Render the file and remember the result in `last`. If the file cannot be
+/// read, the last successful rendering is returned with a banner explaining
+/// why: editors that delete and recreate the file on save leave it missing for
+/// a moment, so this is reported and waited for rather than treated as fatal.
+fn read_and_render(file_path: &Path, options: &RenderOptions, last: &mut Rendered) -> Rendered {
+    match std::fs::read_to_string(file_path) {
+        Ok(markdown) => {
+            *last = render_markdown(&markdown, options);
+            last.clone()
+        }
+        Err(e) => {
+            let filename = file_path.display();
+            let message = match e.kind() {
+                io::ErrorKind::NotFound => {
+                    format!("{filename} not found — waiting for it to come back")
+                }
+                _ => format!("Could not read {filename}: {e}"),
+            };
+            let blocks = std::iter::once(status_banner(&message))
+                .chain(last.blocks.iter().cloned())
+                .collect();
+            Arc::new(Rendering {
+                metadata: last.metadata.clone(),
+                blocks,
+            })
+        }
+    }
 }
 
-async fn check_file(file_path: &PathBuf, chan_tx: &Sender<String>) -> Result<()> {
-    let mut interval = interval(Duration::from_millis(INTERVAL_WATCH_MSEC));
-    let mut previous_mtime = SystemTime::UNIX_EPOCH;
+/// A notice shown above the last rendering when the file cannot be read.
+fn status_banner(message: &str) -> String {
+    format!(
+        "<div class=\"mdr-banner\">{}</div>\n",
+        markdown::escape(message)
+    )
+}
 
+/// Render the file again whenever it changes on disk, or the buffer pushed by
+/// an editor while there is one.
+async fn check_file(
+    mut file_path: PathBuf,
+    mut watcher: FileWatcher,
+    mut last: Rendered,
+    options: &RenderOptions,
+    follow_renames: bool,
+    mut buffer_rx: Receiver<Option<String>>,
+    chan_tx: &Sender<Rendered>,
+) -> Result<()> {
     loop {
-        let metadata = metadata(file_path)?;
-        let last_mtime = metadata.modified()?;
+        tokio::select! {
+            changes = watcher.changed() => {
+                for change in changes? {
+                    if let Change::Renamed { from, to } = change {
+                        if follow_renames && from == file_path {
+                            watcher.follow(&from, &to)?;
+                            file_path = to;
+                        }
+                    }
+                }
 
-        if previous_mtime != last_mtime {
-            render_markdown(file_path, chan_tx).await?;
-            previous_mtime = last_mtime;
+                // The editor knows better than the disk while it sends its buffer.
+                if buffer_rx.borrow().is_some() {
+                    continue;
+                }
+            }
+            changed = buffer_rx.changed() => changed?,
         }
 
-        interval.tick().await;
+        let buffer = buffer_rx.borrow_and_update().clone();
+        let rendering = match buffer {
+            Some(markdown) => render_blocking(markdown, options.clone()).await,
+            None => {
+                let (rendering, new_last) =
+                    render_document(file_path.clone(), options.clone(), last).await;
+                last = new_last;
+                rendering
+            }
+        };
+        chan_tx.send(rendering)?;
     }
 }
 
+/// Keep reading markdown from stdin and render it again as more arrives, at
+/// most every `STDIN_THROTTLE_MSEC`.
+async fn stream_stdin(options: RenderOptions, chan_tx: Sender<Rendered>) -> Result<()> {
+    let mut stdin = tokio::io::stdin();
+    let mut input = Vec::new();
+    let mut chunk = [0; 8192];
+
+    loop {
+        let read = stdin.read(&mut chunk).await?;
+        input.extend_from_slice(&chunk[..read]);
+        let mut eof = read == 0;
+
+        let deadline = Instant::now() + Duration::from_millis(STDIN_THROTTLE_MSEC);
+        while !eof {
+            match timeout_at(deadline, stdin.read(&mut chunk)).await {
+                Ok(read) => {
+                    let read = read?;
+                    input.extend_from_slice(&chunk[..read]);
+                    eof = read == 0;
+                }
+                Err(_) => break,
+            }
+        }
+
+        let markdown = String::from_utf8_lossy(&input).to_string();
+        chan_tx.send(render_blocking(markdown, options.clone()).await)?;
+
+        if eof {
+            return Ok(());
+        }
+    }
+}
+
+/// Watch the custom stylesheets and template, and tell pages to reload them
+/// when they change.
+async fn check_customizations(
+    stylesheets: Vec<PathBuf>,
+    template: Option<PathBuf>,
+    reload_tx: Sender<Option<Reload>>,
+) -> Result<()> {
+    let mut files = stylesheets;
+    files.extend(template.clone());
+
+    let mut watcher = FileWatcher::new(&files)?;
+
+    loop {
+        let changes = watcher.changed().await?;
+
+        let template_changed = changes.iter().any(|change| match change {
+            Change::Modified(path) | Change::Renamed { from: path, .. } => {
+                Some(path) == template.as_ref()
+            }
+        });
+
+        let reload = if template_changed {
+            Reload::Page
+        } else {
+            Reload::Stylesheets
+        };
+        reload_tx.send(Some(reload))?;
+    }
+}
+
+/// The arguments controlling how pages are rendered, shared by every command.
+fn page_args<'a, 'b>(
+    extensions_help: &'b str,
+    theme_help: &'b str,
+    highlight_theme_help: &'b str,
+) -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("line-numbers")
+            .long("line-numbers")
+            .help("Number the lines of code blocks"),
+        Arg::with_name("toc")
+            .long("toc")
+            .help("Show a table of contents next to the document"),
+        Arg::with_name("metadata")
+            .long("metadata")
+            .help("Show the front matter of documents in a card above them"),
+        Arg::with_name("extensions")
+            .short("e")
+            .long("extensions")
+            .help(extensions_help)
+            .number_of_values(1)
+            .default_value("all"),
+        Arg::with_name("theme")
+            .short("t")
+            .long("theme")
+            .help(theme_help)
+            .number_of_values(1)
+            .default_value(theme::DEFAULT_THEME),
+        Arg::with_name("css")
+            .long("css")
+            .help("A stylesheet to add to the page, after the built-in ones (can be repeated)")
+            .number_of_values(1)
+            .multiple(true),
+        Arg::with_name("renderer")
+            .long("renderer")
+            .value_name("lang=command")
+            .help("A command rendering the code blocks of a language to SVG, given the code on its input, like dot=\"dot -Tsvg\" (can be repeated)")
+            .number_of_values(1)
+            .multiple(true),
+        Arg::with_name("highlight-theme")
+            .long("highlight-theme")
+            .help(highlight_theme_help)
+            .number_of_values(1),
+    ]
+}
+
 fn parse_args<'a>() -> ArgMatches<'a> {
+    let extensions_help = format!(
+        "The markdown extensions to enable, as a comma-separated list of {} (or all, none)",
+        EXTENSION_NAMES.join(", ")
+    );
+    let theme_help = format!(
+        "The color theme of the preview, one of: {}",
+        theme::names().join(", ")
+    );
+    let highlight_theme_help = format!(
+        "The theme used to highlight code blocks instead of the one matching the color theme, one of: {}",
+        highlight::theme_names().join(", ")
+    );
+    let page_args = page_args(&extensions_help, &theme_help, &highlight_theme_help);
+
     App::new(crate_name!())
         .version(crate_version!())
         .arg(
             Arg::with_name("file")
                 .index(1)
-                .required(true)
-                .help("The path to the markdown file to render"),
+                .help("The path to the markdown file or directory to render, or - to read from stdin (the default when it is piped)"),
         )
         .arg(
             Arg::with_name("ip")
@@ -135,16 +1193,157 @@ fn parse_args<'a>() -> ArgMatches<'a> {
                 .number_of_values(1)
                 .default_value("8080"),
         )
+        .arg(
+            Arg::with_name("cdn")
+                .long("cdn")
+                .help("Load stylesheets and scripts from their CDN instead of the bundled copies"),
+        )
+        .arg(
+            Arg::with_name("stream")
+                .long("stream")
+                .help("Serve markdown read from stdin right away, and update it as more input arrives"),
+        )
+        .arg(
+            Arg::with_name("follow-renames")
+                .long("follow-renames")
+                .help("Keep previewing a file under its new name when it is renamed"),
+        )
+        .arg(
+            Arg::with_name("template")
+                .long("template")
+                .help("A template to render pages with instead of the built-in one")
+                .number_of_values(1),
+        )
+        .args(&page_args)
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Render a markdown file to a self-contained HTML file")
+                .arg(
+                    Arg::with_name("file")
+                        .index(1)
+                        .required(true)
+                        .help("The path to the markdown file to export"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .help("The HTML file to write [default: the markdown file with an .html extension]")
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("embed-images")
+                        .long("embed-images")
+                        .help("Inline local images as data URIs"),
+                )
+                .arg(
+                    Arg::with_name("watch")
+                        .long("watch")
+                        .help("Export again whenever the file, its stylesheets or its embedded images change"),
+                )
+                .args(&page_args),
+        )
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("Render a directory of markdown files to a static HTML site")
+                .arg(
+                    Arg::with_name("dir")
+                        .index(1)
+                        .required(true)
+                        .help("The path to the directory of markdown files"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .help("The directory to write the site to")
+                        .number_of_values(1)
+                        .default_value("site"),
+                )
+                .args(&page_args),
+        )
         .get_matches()
 }
 
+/// How pages are rendered and styled, as given on the command line.
+struct PageOptions {
+    render: RenderOptions,
+    theme: String,
+    /// The highlighting styles of every color theme.
+    highlight_css: String,
+    custom_css: Vec<PathBuf>,
+}
+
+impl PageOptions {
+    fn from_args(args: &ArgMatches) -> Result<Self> {
+        let extensions = Extensions::parse(args.value_of("extensions").unwrap())?;
+        let theme = args.value_of("theme").unwrap();
+        theme::validate(theme)?;
+        let highlight_css = theme::highlight_css(args.value_of("highlight-theme"))?;
+        let custom_css = args
+            .values_of("css")
+            .into_iter()
+            .flatten()
+            .map(|file| Path::new(file).canonicalize())
+            .collect::<io::Result<Vec<_>>>()?;
+        let renderers = args
+            .values_of("renderer")
+            .into_iter()
+            .flatten()
+            .map(Renderer::parse)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(PageOptions {
+            render: RenderOptions {
+                extensions,
+                line_numbers: args.is_present("line-numbers"),
+                toc: args.is_present("toc"),
+                metadata: args.is_present("metadata"),
+                renderers,
+            },
+            theme: theme.to_string(),
+            highlight_css,
+            custom_css,
+        })
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = parse_args();
 
-    let file = args.value_of("file").unwrap();
+    match args.subcommand() {
+        ("export", Some(args)) => export::run(args).await,
+        ("build", Some(args)) => build::run(args),
+        _ => serve(&args).await,
+    }
+}
+
+/// Serve a live preview of a file, a directory or stdin.
+async fn serve(args: &ArgMatches<'_>) -> Result<()> {
+    let file = match args.value_of("file") {
+        Some(file) => file,
+        None if !io::stdin().is_terminal() => STDIN_PATH,
+        None => {
+            return Err(anyhow!(
+                "no file given (pass a file, a directory, or - to read from stdin)"
+            ))
+        }
+    };
     let ip = args.value_of("ip").unwrap();
     let port = args.value_of("port").unwrap();
+    let PageOptions {
+        render: options,
+        theme,
+        highlight_css,
+        custom_css,
+    } = PageOptions::from_args(args)?;
+    let follow_renames = args.is_present("follow-renames");
+    let cdn = args.is_present("cdn");
+    let template = args
+        .value_of("template")
+        .map(|file| Path::new(file).canonicalize())
+        .transpose()?;
 
     let host = format!("{ip}:{port}");
     let host: SocketAddr = match host.parse() {
@@ -152,42 +1351,237 @@ async fn main() -> Result<()> {
         Err(_) => return Err(anyhow!("could not parse ip/port")),
     };
 
-    if !Path::new(&file).exists() {
+    let path = PathBuf::from(file);
+    if file != STDIN_PATH && !path.exists() {
         return Err(anyhow!("file does not exist"));
     }
 
-    let file_path = PathBuf::from(file);
+    // Links and images in markdown read from stdin are relative to the
+    // current directory.
+    let (root, file_path) = if file == STDIN_PATH {
+        (PathBuf::from("."), Some(path))
+    } else if path.is_dir() {
+        (path, None)
+    } else {
+        let root = match path.parent() {
+            Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        (root, path.file_name().map(PathBuf::from))
+    };
 
-    let (chan_tx, chan_rx) = channel(String::new());
+    let documents = Documents::new(&root, options.clone(), follow_renames)?;
 
-    task::spawn(async move {
-        if let Err(e) = check_file(&file_path, &chan_tx).await {
-            eprintln!("{e}");
-            std::process::exit(1);
+    // Kept until the server stops, so that pages stay connected once stdin is
+    // read entirely.
+    let _stdin_tx = if file == STDIN_PATH {
+        let (chan_tx, chan_rx) = if args.is_present("stream") {
+            let (chan_tx, chan_rx) = channel(Rendered::default());
+            let stream_tx = chan_tx.clone();
+            task::spawn(async move {
+                if let Err(e) = stream_stdin(options, stream_tx).await {
+                    eprintln!("Error: stopped reading stdin: {e}");
+                }
+            });
+            (chan_tx, chan_rx)
+        } else {
+            let markdown = io::read_to_string(io::stdin())?;
+            channel(render_markdown(&markdown, &options))
+        };
+        documents.insert(STDIN_PATH, Document::new(chan_rx).0);
+        Some(chan_tx)
+    } else {
+        // Start watching right away in single file mode, as before.
+        if let Some(file_path) = &file_path {
+            documents.subscribe(&file_path.to_string_lossy()).await;
         }
-    });
+        None
+    };
+
+    let (reload_tx, reload_rx) = channel(None);
+
+    if !custom_css.is_empty() || template.is_some() {
+        let stylesheets = custom_css.clone();
+        let template = template.clone();
+        task::spawn(async move {
+            if let Err(e) = check_customizations(stylesheets, template, reload_tx).await {
+                eprintln!("Error: stopped watching stylesheets and template: {e}");
+            }
+        });
+    }
+
+    let filename = if file == STDIN_PATH { "stdin" } else { file };
 
     let config = Config {
-        filename: file.to_string(),
+        filename: filename.to_string(),
         ip: ip.to_string(),
         port: port.to_string(),
+        origin: format!("http://{host}"),
+        root,
+        file: file_path,
+        assets: AssetUrls::new(cdn, custom_css.len()),
+        highlight_css,
+        theme,
+        custom_css,
+        template,
     };
 
     let app = Router::new()
         .route("/", get(index_route))
+        .route("/_assets/highlight.css", get(highlight_css_route))
+        .route("/_assets/*name", get(bundled_asset_route))
+        .route("/_custom/:index", get(custom_css_route))
+        .route("/view/*path", get(view_route))
+        .route("/content", get(content_route))
         .route("/websocket", get(websocket_route))
+        .route("/editor", get(editor_route))
+        .route("/buffer", put(put_buffer_route).delete(delete_buffer_route))
+        .fallback(asset_route)
+        .layer(middleware::from_fn(check_request))
         .layer(Extension(config))
-        .layer(Extension(chan_rx));
+        .layer(Extension(documents))
+        .layer(Extension(reload_rx));
 
     println!("Serving file on http://{host}");
 
     let mut cmd = Command::new("xdg-open");
-    cmd.arg(&format!("http://{host}"));
+    cmd.arg(format!("http://{host}"));
     cmd.spawn()?;
 
     axum::Server::bind(&host)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory holding `root/page.md`, `root/img/a.png`, `root/.env`,
+    /// `root/.git/config` and `secret.txt` next to the root.
+    fn fixture(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mdr-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let root = dir.join("root");
+        std::fs::create_dir_all(root.join("img")).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join("page.md"), "# Page").unwrap();
+        std::fs::write(root.join("img/a.png"), "png").unwrap();
+        std::fs::write(root.join(".env"), "SECRET=1").unwrap();
+        std::fs::write(root.join(".git/config"), "[core]").unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+        root
+    }
+
+    #[test]
+    fn resolve_files_of_the_root() {
+        let root = fixture("resolve");
+        assert!(resolve_asset(&root, "page.md").is_some());
+        assert!(resolve_asset(&root, "/img/a.png").is_some());
+        assert!(resolve_asset(&root, "./img/a.png").is_some());
+        assert!(resolve_asset(&root, "img").is_none());
+        assert!(resolve_asset(&root, "missing.png").is_none());
+    }
+
+    #[test]
+    fn parent_directories_are_refused() {
+        let root = fixture("parent");
+        assert!(resolve_asset(&root, "../secret.txt").is_none());
+        assert!(resolve_asset(&root, "img/../../secret.txt").is_none());
+        assert!(resolve_asset(&root, "img/../page.md").is_none());
+        assert_eq!(relative_path("/../secret.txt"), None);
+
+        let decoded = decode_path("/%2e%2e/secret.txt").unwrap();
+        assert_eq!(decoded, "/../secret.txt");
+        assert!(resolve_asset(&root, &decoded).is_none());
+        let decoded = decode_path("/img/%2E%2E/%2e%2e/secret.txt").unwrap();
+        assert!(resolve_asset(&root, &decoded).is_none());
+    }
+
+    #[test]
+    fn hidden_files_are_refused() {
+        let root = fixture("hidden");
+        assert!(resolve_asset(&root, ".env").is_none());
+        assert!(resolve_asset(&root, "/.git/config").is_none());
+        assert!(resolve_asset(&root, &decode_path("/%2eenv").unwrap()).is_none());
+        assert_eq!(relative_path(".git/config"), None);
+        assert_eq!(relative_path("img/a.png"), Some(PathBuf::from("img/a.png")));
+    }
+
+    fn test_config(ip: &str, port: &str) -> Config {
+        Config {
+            filename: String::new(),
+            ip: ip.to_string(),
+            port: port.to_string(),
+            origin: format!("http://{ip}:{port}"),
+            root: PathBuf::new(),
+            file: None,
+            assets: AssetUrls::new(false, 0),
+            highlight_css: String::new(),
+            theme: String::new(),
+            custom_css: Vec::new(),
+            template: None,
+        }
+    }
+
+    #[test]
+    fn served_hosts() {
+        let config = test_config("127.0.0.1", "8080");
+        assert!(is_served_host("127.0.0.1:8080", &config));
+        assert!(is_served_host("localhost:8080", &config));
+        assert!(is_served_host("[::1]:8080", &config));
+        assert!(!is_served_host("localhost:8081", &config));
+        assert!(!is_served_host("localhost", &config));
+        assert!(!is_served_host("evil.example:8080", &config));
+        assert!(!is_served_host("192.168.1.2:8080", &config));
+
+        let config = test_config("0.0.0.0", "8080");
+        assert!(is_served_host("192.168.1.2:8080", &config));
+        assert!(is_served_host("[fe80::1]:8080", &config));
+        assert!(!is_served_host("evil.example:8080", &config));
+    }
+
+    #[test]
+    fn allowed_origins() {
+        let config = test_config("127.0.0.1", "8080");
+        let local = SocketAddr::from(([127, 0, 0, 1], 5000));
+        let remote = SocketAddr::from(([192, 168, 1, 2], 5000));
+        let origin = |origin: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ORIGIN, origin.parse().unwrap());
+            headers
+        };
+
+        assert!(is_allowed(&HeaderMap::new(), local, &config));
+        assert!(!is_allowed(&HeaderMap::new(), remote, &config));
+        assert!(is_allowed(
+            &origin("http://localhost:8080"),
+            remote,
+            &config
+        ));
+        assert!(!is_allowed(
+            &origin("http://evil.example:8080"),
+            local,
+            &config
+        ));
+        assert!(!is_allowed(
+            &origin("https://127.0.0.1:8080"),
+            local,
+            &config
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_the_root_are_refused() {
+        let root = fixture("symlink");
+        std::os::unix::fs::symlink(root.join("../secret.txt"), root.join("out.txt")).unwrap();
+        std::os::unix::fs::symlink(root.join(".env"), root.join("env.txt")).unwrap();
+        std::os::unix::fs::symlink(root.join("img/a.png"), root.join("b.png")).unwrap();
+        assert!(resolve_asset(&root, "out.txt").is_none());
+        assert!(resolve_asset(&root, "env.txt").is_none());
+        assert!(resolve_asset(&root, "b.png").is_some());
+    }
+}
@@ -0,0 +1,1050 @@
+use std::{borrow::Cow, cell::RefCell, collections::HashSet, fmt, ops::Range};
+
+use anyhow::{anyhow, Result};
+use pulldown_cmark::{
+    BlockQuoteKind, CodeBlockKind, CowStr, Event, HeadingLevel, LinkType, Options, Parser, Tag,
+    TagEnd, TextMergeWithOffset,
+};
+use pulldown_cmark_escape::escape_html;
+
+use crate::{
+    front_matter::{self, Metadata},
+    highlight, math,
+    renderers::Renderer,
+};
+
+/// Names accepted by `--extensions`, in the order they are listed in the help.
+pub const EXTENSION_NAMES: &[&str] = &[
+    "tables",
+    "strikethrough",
+    "tasklists",
+    "footnotes",
+    "heading-attributes",
+    "autolink",
+    "math",
+    "alerts",
+];
+
+/// The set of markdown extensions to enable while rendering.
+#[derive(Clone, Copy, Debug)]
+pub struct Extensions {
+    options: Options,
+    autolink: bool,
+}
+
+impl Extensions {
+    /// Every GitHub-flavored extension, which is what the preview uses by default.
+    pub fn all() -> Self {
+        Extensions {
+            options: Options::ENABLE_TABLES
+                | Options::ENABLE_STRIKETHROUGH
+                | Options::ENABLE_TASKLISTS
+                | Options::ENABLE_FOOTNOTES
+                | Options::ENABLE_HEADING_ATTRIBUTES
+                | Options::ENABLE_MATH
+                | Options::ENABLE_GFM,
+            autolink: true,
+        }
+    }
+
+    /// Plain CommonMark.
+    pub fn none() -> Self {
+        Extensions {
+            options: Options::empty(),
+            autolink: false,
+        }
+    }
+
+    /// Parse a comma-separated list of extension names, or `all` / `none`.
+    pub fn parse(list: &str) -> Result<Self> {
+        match list.trim() {
+            "all" => return Ok(Self::all()),
+            "none" | "" => return Ok(Self::none()),
+            _ => {}
+        }
+
+        let mut extensions = Self::none();
+
+        for name in list.split(',').map(str::trim) {
+            match name {
+                "tables" => extensions.options |= Options::ENABLE_TABLES,
+                "strikethrough" => extensions.options |= Options::ENABLE_STRIKETHROUGH,
+                "tasklists" => extensions.options |= Options::ENABLE_TASKLISTS,
+                "footnotes" => extensions.options |= Options::ENABLE_FOOTNOTES,
+                "heading-attributes" => extensions.options |= Options::ENABLE_HEADING_ATTRIBUTES,
+                "autolink" => extensions.autolink = true,
+                "math" => extensions.options |= Options::ENABLE_MATH,
+                "alerts" => extensions.options |= Options::ENABLE_GFM,
+                _ => {
+                    return Err(anyhow!(
+                        "unknown markdown extension '{name}' (expected one of: all, none, {})",
+                        EXTENSION_NAMES.join(", ")
+                    ))
+                }
+            }
+        }
+
+        Ok(extensions)
+    }
+}
+
+/// Everything that affects how markdown is turned into HTML.
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    pub extensions: Extensions,
+    /// Number the lines of fenced code blocks.
+    pub line_numbers: bool,
+    /// Show a table of contents floating next to the document.
+    pub toc: bool,
+    /// Show the front matter in a card above the document.
+    pub metadata: bool,
+    /// Commands rendering the code blocks of some languages to SVG.
+    pub renderers: Vec<Renderer>,
+}
+
+/// A rendered markdown document.
+#[derive(Debug, Default)]
+pub struct Rendering {
+    /// The fields of its front matter.
+    pub metadata: Metadata,
+    /// Its HTML, split into one fragment per top-level block so that a
+    /// changed document can be sent to the page block by block. Concatenated,
+    /// the fragments are the whole rendering.
+    pub blocks: Vec<String>,
+}
+
+impl Rendering {
+    pub fn html(&self) -> String {
+        self.blocks.concat()
+    }
+
+    /// Whether there are mermaid diagrams for the page to draw.
+    pub fn has_diagrams(&self) -> bool {
+        self.blocks
+            .iter()
+            .any(|block| block.contains("class=\"mermaid\""))
+    }
+
+    /// The title of the page, from the front matter or else `filename`.
+    pub fn title(&self, filename: &str) -> String {
+        front_matter::title(&self.metadata)
+            .unwrap_or(filename)
+            .to_string()
+    }
+}
+
+/// Markers replaced by a table of contents, alone in their paragraph or HTML
+/// block.
+const TOC_MARKERS: &[&str] = &["[[_TOC_]]", "<!-- toc -->"];
+
+/// The link icon GitHub shows next to headings.
+const ANCHOR_ICON: &str = r#"<svg class="octicon octicon-link" viewBox="0 0 16 16" width="16" height="16" aria-hidden="true"><path d="m7.775 3.275 1.25-1.25a3.5 3.5 0 1 1 4.95 4.95l-2.5 2.5a3.5 3.5 0 0 1-4.95 0 .751.751 0 0 1 .018-1.042.751.751 0 0 1 1.042-.018 1.998 1.998 0 0 0 2.83 0l2.5-2.5a2.002 2.002 0 0 0-2.83-2.83l-1.25 1.25a.751.751 0 0 1-1.042-.018.751.751 0 0 1-.018-1.042Zm-4.69 9.64a1.998 1.998 0 0 0 2.83 0l1.25-1.25a.751.751 0 0 1 1.042.018.751.751 0 0 1 .018 1.042l-1.25 1.25a3.5 3.5 0 1 1-4.95-4.95l2.5-2.5a3.5 3.5 0 0 1 4.95 0 .751.751 0 0 1-.018 1.042.751.751 0 0 1-1.042.018 1.998 1.998 0 0 0-2.83 0l-2.5 2.5a1.998 1.998 0 0 0 0 2.83Z"></path></svg>"#;
+
+/// The icons GitHub shows in the title of alerts.
+const NOTE_ICON: &str = "M0 8a8 8 0 1 1 16 0A8 8 0 0 1 0 8Zm8-6.5a6.5 6.5 0 1 0 0 13 6.5 6.5 0 0 0 0-13ZM6.5 7.75A.75.75 0 0 1 7.25 7h1a.75.75 0 0 1 .75.75v2.75h.25a.75.75 0 0 1 0 1.5h-2a.75.75 0 0 1 0-1.5h.25v-2h-.25a.75.75 0 0 1-.75-.75ZM8 6a1 1 0 1 1 0-2 1 1 0 0 1 0 2Z";
+const TIP_ICON: &str = "M8 1.5c-2.363 0-4 1.69-4 3.75 0 .984.424 1.625.984 2.304l.214.253c.223.264.47.556.673.848.284.411.537.896.621 1.49a.75.75 0 0 1-1.484.211c-.04-.282-.163-.547-.37-.847a8.456 8.456 0 0 0-.542-.68c-.084-.1-.173-.205-.268-.32C3.201 7.75 2.5 6.766 2.5 5.25 2.5 2.31 4.863 0 8 0s5.5 2.31 5.5 5.25c0 1.516-.701 2.5-1.328 3.259-.095.115-.184.22-.268.319-.207.245-.383.453-.541.681-.208.3-.33.565-.37.847a.751.751 0 0 1-1.485-.212c.084-.593.337-1.078.621-1.489.203-.292.45-.584.673-.848.075-.088.147-.173.213-.253.561-.679.985-1.32.985-2.304 0-2.06-1.637-3.75-4-3.75ZM5.75 12h4.5a.75.75 0 0 1 0 1.5h-4.5a.75.75 0 0 1 0-1.5ZM6 15.25a.75.75 0 0 1 .75-.75h2.5a.75.75 0 0 1 0 1.5h-2.5a.75.75 0 0 1-.75-.75Z";
+const IMPORTANT_ICON: &str = "M0 1.75C0 .784.784 0 1.75 0h12.5C15.216 0 16 .784 16 1.75v9.5A1.75 1.75 0 0 1 14.25 13H8.06l-2.573 2.573A1.458 1.458 0 0 1 3 14.543V13H1.75A1.75 1.75 0 0 1 0 11.25Zm1.75-.25a.25.25 0 0 0-.25.25v9.5c0 .138.112.25.25.25h2a.75.75 0 0 1 .75.75v2.19l2.72-2.72a.749.749 0 0 1 .53-.22h6.5a.25.25 0 0 0 .25-.25v-9.5a.25.25 0 0 0-.25-.25Zm7 2.25v2.5a.75.75 0 0 1-1.5 0v-2.5a.75.75 0 0 1 1.5 0ZM9 9a1 1 0 1 1-2 0 1 1 0 0 1 2 0Z";
+const WARNING_ICON: &str = "M6.457 1.047c.659-1.234 2.427-1.234 3.086 0l6.082 11.378A1.75 1.75 0 0 1 14.082 15H1.918a1.75 1.75 0 0 1-1.543-2.575Zm1.763.707a.25.25 0 0 0-.44 0L1.698 13.132a.25.25 0 0 0 .22.368h12.164a.25.25 0 0 0 .22-.368Zm.53 3.996v2.5a.75.75 0 0 1-1.5 0v-2.5a.75.75 0 0 1 1.5 0ZM9 11a1 1 0 1 1-2 0 1 1 0 0 1 2 0Z";
+const CAUTION_ICON: &str = "M4.47.22A.749.749 0 0 1 5 0h6c.199 0 .389.079.53.22l4.25 4.25c.141.14.22.331.22.53v6a.749.749 0 0 1-.22.53l-4.25 4.25A.749.749 0 0 1 11 16H5a.749.749 0 0 1-.53-.22L.22 11.53A.749.749 0 0 1 0 11V5c0-.199.079-.389.22-.53Zm.84 1.28L1.5 5.31v5.38l3.81 3.81h5.38l3.81-3.81V5.31L10.69 1.5ZM8 4a.75.75 0 0 1 .75.75v3.5a.75.75 0 0 1-1.5 0v-3.5A.75.75 0 0 1 8 4Zm0 8a1 1 0 1 1 0-2 1 1 0 0 1 0 2Z";
+
+/// A heading of the document, as listed in the table of contents.
+struct Heading {
+    level: HeadingLevel,
+    id: String,
+    text: String,
+}
+
+/// Render markdown text, which may start with front matter, to HTML.
+pub fn render(markdown: &str, options: &RenderOptions) -> Rendering {
+    let (metadata, markdown) = front_matter::split(markdown);
+    let markdown = if options.extensions.options.contains(Options::ENABLE_GFM) {
+        match alert_containers(&markdown) {
+            Cow::Owned(quoted) => Cow::Owned(quoted),
+            Cow::Borrowed(_) => markdown,
+        }
+    } else {
+        markdown
+    };
+    let markdown = markdown.as_ref();
+
+    let parser = Parser::new_ext(markdown, options.extensions.options).into_offset_iter();
+    let events: Vec<_> = TextMergeWithOffset::new(parser).collect();
+
+    // The table of contents can come before the headings it lists, so they
+    // are all gathered first.
+    let headings = headings(&events);
+    let events = anchor_headings(events, &headings, markdown);
+    let events = CodeBlocks::new(
+        events.into_iter(),
+        options.line_numbers,
+        options.extensions.options.contains(Options::ENABLE_MATH),
+        &options.renderers,
+    );
+
+    // A single HTML writer has to see every event, as it numbers footnotes and
+    // tracks tables across blocks, so the blocks are split while it writes.
+    let blocks = RefCell::new(Vec::new());
+    let events = SplitBlocks {
+        events,
+        depth: 0,
+        blocks: &blocks,
+    };
+
+    let events = SourceLines::new(events, markdown);
+    let events = Autolink::new(events, options.extensions.autolink);
+    let _ = pulldown_cmark::html::write_html_fmt(BlockWriter(&blocks), events);
+
+    let mut blocks = blocks.into_inner();
+    if options.toc && !headings.is_empty() {
+        blocks.insert(0, table_of_contents(&headings, "mdr-toc mdr-toc-panel"));
+    }
+    if options.metadata && !metadata.is_empty() {
+        blocks.insert(0, front_matter::card(&metadata));
+    }
+
+    Rendering { metadata, blocks }
+}
+
+/// The headings of a document, with the id they are given: the one set with
+/// a heading attribute, or a slug of their text like GitHub makes, numbered
+/// when it is already taken.
+fn headings(events: &[(Event, Range<usize>)]) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let mut current: Option<(HeadingLevel, Option<&str>, String)> = None;
+
+    for (event, _) in events {
+        match event {
+            Event::Start(Tag::Heading { level, id, .. }) => {
+                current = Some((*level, id.as_deref(), String::new()));
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, _, heading_text)) = &mut current {
+                    heading_text.push_str(text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((level, id, text)) = current.take() {
+                    headings.push((level, id, text));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Set ids take precedence over slugs, wherever they are.
+    let mut taken: HashSet<String> = headings
+        .iter()
+        .filter_map(|(_, id, _)| id.map(str::to_string))
+        .collect();
+
+    headings
+        .into_iter()
+        .map(|(level, id, text)| {
+            let id = match id {
+                Some(id) => id.to_string(),
+                None => {
+                    let slug = slug(&text);
+                    let mut id = slug.clone();
+                    let mut n = 0;
+                    while taken.contains(&id) {
+                        n += 1;
+                        id = format!("{slug}-{n}");
+                    }
+                    taken.insert(id.clone());
+                    id
+                }
+            };
+            Heading {
+                level,
+                id,
+                text: text.trim().to_string(),
+            }
+        })
+        .collect()
+}
+
+/// The GitHub slug of a heading: lowercase, with spaces turned into dashes and
+/// punctuation dropped.
+fn slug(text: &str) -> String {
+    let slug: String = text
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            c if c.is_whitespace() => Some('-'),
+            _ => None,
+        })
+        .collect();
+
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug
+    }
+}
+
+/// Give every heading its id and an anchor linking to it, and replace the
+/// table of contents markers.
+fn anchor_headings<'a>(
+    events: Vec<(Event<'a>, Range<usize>)>,
+    headings: &[Heading],
+    source: &str,
+) -> Vec<(Event<'a>, Range<usize>)> {
+    let mut anchored = Vec::with_capacity(events.len() + headings.len());
+    let mut ids = headings.iter().map(|heading| heading.id.as_str());
+    let mut events = events.into_iter();
+
+    while let Some((event, range)) = events.next() {
+        match event {
+            // `headings` comes from these very events, so the ids line up.
+            Event::Start(Tag::Heading {
+                level,
+                id,
+                classes,
+                attrs,
+            }) => {
+                let id = ids
+                    .next()
+                    .map_or(id, |id| Some(CowStr::from(id.to_string())));
+                let anchor = id.as_ref().map(|id| {
+                    format!(
+                        "<a class=\"anchor\" aria-hidden=\"true\" href=\"#{}\">{ANCHOR_ICON}</a>",
+                        escape(id)
+                    )
+                });
+                anchored.push((
+                    Event::Start(Tag::Heading {
+                        level,
+                        id,
+                        classes,
+                        attrs,
+                    }),
+                    range.clone(),
+                ));
+                anchored
+                    .extend(anchor.map(|anchor| (Event::InlineHtml(CowStr::from(anchor)), range)));
+            }
+            Event::Start(Tag::Paragraph | Tag::HtmlBlock)
+                if TOC_MARKERS.contains(&source[range.clone()].trim()) =>
+            {
+                let end = match event {
+                    Event::Start(Tag::Paragraph) => TagEnd::Paragraph,
+                    _ => TagEnd::HtmlBlock,
+                };
+                events.by_ref().find(|(event, _)| *event == Event::End(end));
+                let toc = table_of_contents(headings, "mdr-toc");
+                anchored.push((Event::Html(CowStr::from(toc)), range));
+            }
+            event => anchored.push((event, range)),
+        }
+    }
+
+    anchored
+}
+
+/// Nested lists of links to the headings, following their levels.
+fn table_of_contents(headings: &[Heading], class: &str) -> String {
+    let mut html = format!("<nav class=\"{class}\">\n");
+    let mut levels: Vec<HeadingLevel> = Vec::new();
+
+    for heading in headings {
+        while levels.len() > 1 && levels.last() > Some(&heading.level) {
+            html.push_str("</li>\n</ul>\n");
+            levels.pop();
+        }
+        // A document starting at a deeper level than it goes on with: the
+        // outer list is for the shallowest headings.
+        if let [outer] = levels.as_mut_slice() {
+            *outer = (*outer).min(heading.level);
+        }
+        match levels.last() {
+            Some(&level) if level >= heading.level => html.push_str("</li>\n"),
+            _ => {
+                html.push_str("<ul>\n");
+                levels.push(heading.level);
+            }
+        }
+        html.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>",
+            escape(&heading.id),
+            escape(&heading.text)
+        ));
+    }
+
+    for _ in levels {
+        html.push_str("</li>\n</ul>\n");
+    }
+    html.push_str("</nav>\n");
+
+    html
+}
+
+/// Escape text for use in HTML content or attribute values.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    let _ = escape_html(&mut escaped, text);
+    escaped
+}
+
+/// Starts a new block in `blocks` whenever an event begins a top-level block.
+/// The HTML writer is done with an event before it asks for the next one, so
+/// everything it writes lands in the block the event belongs to, as long as
+/// the adapters between here and the writer never read ahead.
+struct SplitBlocks<'b, I> {
+    events: I,
+    depth: usize,
+    blocks: &'b RefCell<Vec<String>>,
+}
+
+impl<'a, I: Iterator<Item = (Event<'a>, Range<usize>)>> Iterator for SplitBlocks<'_, I> {
+    type Item = (Event<'a>, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.events.next()?;
+
+        if self.depth == 0 {
+            self.blocks.borrow_mut().push(String::new());
+        }
+        match event.0 {
+            Event::Start(_) => self.depth += 1,
+            Event::End(_) => self.depth -= 1,
+            _ => {}
+        }
+
+        Some(event)
+    }
+}
+
+/// Writes HTML to the last block started by `SplitBlocks`.
+struct BlockWriter<'b>(&'b RefCell<Vec<String>>);
+
+impl fmt::Write for BlockWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut blocks = self.0.borrow_mut();
+        match blocks.last_mut() {
+            Some(block) => block.push_str(s),
+            None => blocks.push(s.to_string()),
+        }
+        Ok(())
+    }
+}
+
+/// Turns bare `http://`, `https://` and `www.` URLs found in text into links,
+/// like GitHub's autolink extension does.
+struct Autolink<'a, I> {
+    events: I,
+    enabled: bool,
+    pending: Vec<Event<'a>>,
+    /// Depth of links and images, whose text must be left alone. Code blocks
+    /// are already turned into HTML by the time text gets here.
+    verbatim: usize,
+}
+
+impl<'a, I> Autolink<'a, I> {
+    fn new(events: I, enabled: bool) -> Self {
+        Autolink {
+            events,
+            enabled,
+            pending: Vec::new(),
+            verbatim: 0,
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Iterator for Autolink<'a, I> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.pending.pop() {
+            return Some(event);
+        }
+
+        let event = self.events.next()?;
+        match &event {
+            Event::Start(Tag::Link { .. } | Tag::Image { .. }) => self.verbatim += 1,
+            Event::End(TagEnd::Link | TagEnd::Image) => self.verbatim -= 1,
+            Event::Text(text) if self.enabled && self.verbatim == 0 => {
+                let mut events = linkify(text);
+                if events.len() > 1 {
+                    events.reverse();
+                    self.pending = events;
+                    return self.pending.pop();
+                }
+            }
+            _ => {}
+        }
+
+        Some(event)
+    }
+}
+
+/// Replaces code blocks with their syntax-highlighted HTML, or the output of
+/// the renderer set for their language, `math` code blocks and math spans with
+/// MathML, and leaves `mermaid` code blocks to the page.
+struct CodeBlocks<'r, I> {
+    events: I,
+    line_numbers: bool,
+    /// Render `math` code blocks, along with the math extension.
+    math: bool,
+    renderers: &'r [Renderer],
+}
+
+impl<'r, I> CodeBlocks<'r, I> {
+    fn new(events: I, line_numbers: bool, math: bool, renderers: &'r [Renderer]) -> Self {
+        CodeBlocks {
+            events,
+            line_numbers,
+            math,
+            renderers,
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item = (Event<'a>, Range<usize>)>> Iterator for CodeBlocks<'_, I> {
+    type Item = (Event<'a>, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (kind, range) = match self.events.next()? {
+            (Event::Start(Tag::CodeBlock(kind)), range) => (kind, range),
+            (Event::InlineMath(latex), range) => {
+                return Some((Event::InlineHtml(math::render(&latex, false).into()), range))
+            }
+            (Event::DisplayMath(latex), range) => {
+                return Some((Event::InlineHtml(math::render(&latex, true).into()), range))
+            }
+            event => return Some(event),
+        };
+
+        let lang = match &kind {
+            CodeBlockKind::Fenced(info) => info
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .next()
+                .unwrap_or_default(),
+            CodeBlockKind::Indented => "",
+        };
+
+        let mut code = String::new();
+        for (event, _) in self.events.by_ref() {
+            match event {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => break,
+                _ => {}
+            }
+        }
+
+        let renderer = self.renderers.iter().find(|renderer| renderer.lang == lang);
+        let html = match (renderer, lang) {
+            (Some(renderer), _) => renderer.render(&code),
+            (None, "math") if self.math => format!("{}\n", math::render(&code, true)),
+            // Drawn by the page, with mermaid.
+            (None, "mermaid") => format!("<pre class=\"mermaid\">{}</pre>\n", escape(&code)),
+            (None, _) => highlight::highlight(&code, lang, self.line_numbers),
+        };
+        Some((Event::Html(CowStr::from(html)), range))
+    }
+}
+
+/// Marks block elements with the line of the source they start on, as a
+/// `data-source-line` attribute, so that the preview can be scrolled along
+/// with an editor.
+///
+/// The HTML writer has no way to add attributes to most elements, so their
+/// tags are written here instead, and GitHub alerts the way GitHub writes
+/// them. Tables are left alone as the writer keeps track of their cells, and
+/// so is raw HTML.
+struct SourceLines<I> {
+    events: I,
+    /// Byte offset of the start of every line.
+    line_starts: Vec<usize>,
+    in_html_block: bool,
+}
+
+impl<I> SourceLines<I> {
+    fn new(events: I, source: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        SourceLines {
+            events,
+            line_starts,
+            in_html_block: false,
+        }
+    }
+
+    fn line(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset)
+    }
+}
+
+impl<'a, I: Iterator<Item = (Event<'a>, Range<usize>)>> Iterator for SourceLines<I> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (event, range) = self.events.next()?;
+        let line = self.line(range.start);
+        let attr = format!(" data-source-line=\"{line}\"");
+
+        let html = match event {
+            Event::Start(Tag::Heading {
+                level,
+                id,
+                classes,
+                mut attrs,
+            }) => {
+                attrs.push((
+                    CowStr::Borrowed("data-source-line"),
+                    Some(CowStr::from(line.to_string())),
+                ));
+                return Some(Event::Start(Tag::Heading {
+                    level,
+                    id,
+                    classes,
+                    attrs,
+                }));
+            }
+            Event::Start(Tag::Paragraph) => format!("<p{attr}>"),
+            Event::End(TagEnd::Paragraph) => "</p>\n".to_string(),
+            Event::Start(Tag::BlockQuote(None)) => format!("<blockquote{attr}>\n"),
+            Event::End(TagEnd::BlockQuote(None)) => "</blockquote>\n".to_string(),
+            Event::Start(Tag::BlockQuote(Some(kind))) => alert(kind, &attr),
+            Event::End(TagEnd::BlockQuote(Some(_))) => "</div>\n".to_string(),
+            Event::Start(Tag::List(None)) => format!("<ul{attr}>\n"),
+            Event::Start(Tag::List(Some(1))) => format!("<ol{attr}>\n"),
+            Event::Start(Tag::List(Some(start))) => format!("<ol start=\"{start}\"{attr}>\n"),
+            Event::End(TagEnd::List(false)) => "</ul>\n".to_string(),
+            Event::End(TagEnd::List(true)) => "</ol>\n".to_string(),
+            Event::Start(Tag::Item) => format!("<li{attr}>"),
+            Event::End(TagEnd::Item) => "</li>\n".to_string(),
+            Event::Rule => format!("<hr{attr} />\n"),
+            Event::Start(Tag::HtmlBlock) => {
+                self.in_html_block = true;
+                return Some(event);
+            }
+            Event::End(TagEnd::HtmlBlock) => {
+                self.in_html_block = false;
+                return Some(event);
+            }
+            // Block HTML outside of raw HTML blocks is generated by mdr, like
+            // highlighted code blocks.
+            Event::Html(html) if !self.in_html_block => insert_attribute(&html, &attr),
+            event => return Some(event),
+        };
+
+        Some(Event::Html(CowStr::from(html)))
+    }
+}
+
+/// The start of a GitHub alert, with its icon and title, as GitHub writes it.
+fn alert(kind: BlockQuoteKind, attr: &str) -> String {
+    let (name, title, icon) = match kind {
+        BlockQuoteKind::Note => ("note", "Note", NOTE_ICON),
+        BlockQuoteKind::Tip => ("tip", "Tip", TIP_ICON),
+        BlockQuoteKind::Important => ("important", "Important", IMPORTANT_ICON),
+        BlockQuoteKind::Warning => ("warning", "Warning", WARNING_ICON),
+        BlockQuoteKind::Caution => ("caution", "Caution", CAUTION_ICON),
+    };
+
+    format!(
+        "<div class=\"markdown-alert markdown-alert-{name}\"{attr}>\n<p class=\"markdown-alert-title\"><svg class=\"octicon\" viewBox=\"0 0 16 16\" width=\"16\" height=\"16\" aria-hidden=\"true\"><path d=\"{icon}\"></path></svg>{title}</p>\n"
+    )
+}
+
+/// Turn `:::note` ... `:::` containers into GitHub alerts, by quoting their
+/// lines under a `> [!NOTE]` line. Every line stays on its line, so that the
+/// source lines of the blocks do not change.
+fn alert_containers(markdown: &str) -> Cow<'_, str> {
+    if !markdown.contains(":::") {
+        return Cow::Borrowed(markdown);
+    }
+
+    let mut quoted = String::with_capacity(markdown.len());
+    let mut in_container = false;
+    // The character and length of the fence of the code block we are in.
+    let mut fence: Option<(char, usize)> = None;
+
+    for line in markdown.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\r', '\n']);
+        let ending = &line[content.len()..];
+        let trimmed = content.trim();
+
+        let fence_char = trimmed.chars().next().filter(|c| *c == '`' || *c == '~');
+        let fence_len = fence_char.map_or(0, |c| trimmed.chars().take_while(|&d| d == c).count());
+        match (fence, fence_char) {
+            (None, Some(c)) if fence_len >= 3 => fence = Some((c, fence_len)),
+            (Some((c, len)), Some(d))
+                if c == d && fence_len >= len && trimmed[fence_len..].trim().is_empty() =>
+            {
+                fence = None
+            }
+            (Some(_), _) => {}
+            (None, _) => {
+                let kind = trimmed
+                    .strip_prefix(":::")
+                    .map(|kind| kind.trim().to_ascii_uppercase());
+                match kind.as_deref() {
+                    Some(kind @ ("NOTE" | "TIP" | "IMPORTANT" | "WARNING" | "CAUTION"))
+                        if !in_container =>
+                    {
+                        quoted.push_str(&format!("> [!{kind}]{ending}"));
+                        in_container = true;
+                        continue;
+                    }
+                    Some("") if in_container => {
+                        quoted.push_str(ending);
+                        in_container = false;
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if in_container {
+            quoted.push_str(if content.is_empty() { ">" } else { "> " });
+        }
+        quoted.push_str(line);
+    }
+
+    Cow::Owned(quoted)
+}
+
+/// Add an attribute to the first tag of an HTML fragment.
+fn insert_attribute(html: &str, attr: &str) -> String {
+    let name_end = html
+        .find('<')
+        .filter(|&i| html[i + 1..].starts_with(|c: char| c.is_ascii_alphabetic()))
+        .map(|i| {
+            html[i + 1..]
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .map_or(html.len(), |end| i + 1 + end)
+        });
+
+    match name_end {
+        Some(end) => format!("{}{attr}{}", &html[..end], &html[end..]),
+        None => html.to_string(),
+    }
+}
+
+/// Split text into plain text and link events around the URLs it contains.
+fn linkify<'a>(text: &str) -> Vec<Event<'a>> {
+    let mut events = Vec::new();
+    let mut rest = text;
+
+    while let Some((start, end)) = find_url(rest) {
+        if start > 0 {
+            events.push(Event::Text(CowStr::from(rest[..start].to_string())));
+        }
+
+        let url = &rest[start..end];
+        let dest_url = if url.starts_with("www.") {
+            format!("http://{url}")
+        } else {
+            url.to_string()
+        };
+
+        events.push(Event::Start(Tag::Link {
+            link_type: LinkType::Autolink,
+            dest_url: CowStr::from(dest_url),
+            title: CowStr::Borrowed(""),
+            id: CowStr::Borrowed(""),
+        }));
+        events.push(Event::Text(CowStr::from(url.to_string())));
+        events.push(Event::End(TagEnd::Link));
+
+        rest = &rest[end..];
+    }
+
+    if !rest.is_empty() {
+        events.push(Event::Text(CowStr::from(rest.to_string())));
+    }
+
+    events
+}
+
+/// Find the byte range of the first URL in `text`.
+fn find_url(text: &str) -> Option<(usize, usize)> {
+    let mut offset = 0;
+
+    while offset < text.len() {
+        let start = ["https://", "http://", "www."]
+            .iter()
+            .filter_map(|prefix| {
+                text[offset..]
+                    .find(prefix)
+                    .map(|i| (offset + i, prefix.len()))
+            })
+            .min()?;
+
+        let (start, prefix_len) = start;
+        let at_word_boundary = text[..start]
+            .chars()
+            .next_back()
+            .is_none_or(|c| c.is_whitespace() || "*_~(".contains(c));
+
+        let end = start
+            + text[start..]
+                .find(|c: char| c.is_whitespace() || c == '<')
+                .unwrap_or(text.len() - start);
+        let end = trim_url_end(&text[..end], start);
+
+        if at_word_boundary
+            && end > start + prefix_len
+            && text[start + prefix_len..end].contains(|c: char| c.is_alphanumeric())
+        {
+            return Some((start, end));
+        }
+
+        offset = start + prefix_len;
+    }
+
+    None
+}
+
+/// Drop trailing punctuation and unbalanced closing parentheses from a URL
+/// candidate, following the GitHub autolink rules.
+fn trim_url_end(text: &str, start: usize) -> usize {
+    let mut end = text.len();
+
+    loop {
+        let url = &text[start..end];
+        let Some(last) = url.chars().next_back() else {
+            return end;
+        };
+
+        if "?!.,:*_~'\"".contains(last) {
+            end -= last.len_utf8();
+        } else if last == ')' && url.matches(')').count() > url.matches('(').count() {
+            end -= 1;
+        } else if last == ';' {
+            // Trailing entity references such as `&amp;` are not part of the URL.
+            match url.rfind('&') {
+                Some(amp)
+                    if url[amp + 1..url.len() - 1]
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric()) =>
+                {
+                    end = start + amp
+                }
+                _ => end -= 1,
+            }
+        } else {
+            return end;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(text: &str) -> Option<&str> {
+        find_url(text).map(|(start, end)| &text[start..end])
+    }
+
+    #[test]
+    fn find_urls() {
+        assert_eq!(
+            url("see https://example.com for more"),
+            Some("https://example.com")
+        );
+        assert_eq!(url("http://a.b/c?d=e"), Some("http://a.b/c?d=e"));
+        assert_eq!(
+            url("www.commonmark.org/help"),
+            Some("www.commonmark.org/help")
+        );
+        assert_eq!(url("at https://a.b<br>"), Some("https://a.b"));
+        assert_eq!(url("no link here"), None);
+    }
+
+    #[test]
+    fn urls_start_at_word_boundaries() {
+        assert_eq!(url("xhttps://example.com"), None);
+        assert_eq!(url("_www.example.com_"), Some("www.example.com"));
+        assert_eq!(url("(https://example.com)"), Some("https://example.com"));
+    }
+
+    #[test]
+    fn urls_need_more_than_a_prefix() {
+        assert_eq!(url("http:// and www."), None);
+        assert_eq!(
+            url("www. and https://example.com"),
+            Some("https://example.com")
+        );
+        assert_eq!(url("https://... then"), None);
+    }
+
+    #[test]
+    fn trailing_punctuation_is_not_part_of_urls() {
+        assert_eq!(url("Visit www.commonmark.org."), Some("www.commonmark.org"));
+        assert_eq!(
+            url("Visit www.commonmark.org/a.b."),
+            Some("www.commonmark.org/a.b")
+        );
+        assert_eq!(
+            url("is it https://example.com?!"),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            url("see https://example.com/a'"),
+            Some("https://example.com/a")
+        );
+        assert_eq!(url("**https://example.com**"), Some("https://example.com"));
+    }
+
+    #[test]
+    fn unbalanced_parentheses_end_urls() {
+        assert_eq!(
+            url("www.google.com/search?q=Markup+(business)"),
+            Some("www.google.com/search?q=Markup+(business)")
+        );
+        assert_eq!(
+            url("(www.google.com/search?q=Markup+(business))"),
+            Some("www.google.com/search?q=Markup+(business)")
+        );
+        assert_eq!(
+            url("www.google.com/search?q=(business))+ok"),
+            Some("www.google.com/search?q=(business))+ok")
+        );
+    }
+
+    #[test]
+    fn trailing_entity_references_end_urls() {
+        assert_eq!(
+            url("www.google.com/search?q=commonmark&hl=en"),
+            Some("www.google.com/search?q=commonmark&hl=en")
+        );
+        assert_eq!(
+            url("www.google.com/search?q=commonmark&hl;"),
+            Some("www.google.com/search?q=commonmark")
+        );
+        assert_eq!(url("https://a.b/c;"), Some("https://a.b/c"));
+    }
+
+    #[test]
+    fn trim_url_ends() {
+        let text = "see https://example.com).";
+        assert_eq!(&text[..trim_url_end(text, 4)], "see https://example.com");
+        let text = "https://example.com/&amp;";
+        assert_eq!(trim_url_end(text, 0), "https://example.com/".len());
+    }
+
+    fn render_all(markdown: &str) -> String {
+        let options = RenderOptions {
+            extensions: Extensions::all(),
+            line_numbers: false,
+            toc: false,
+            metadata: false,
+            renderers: Vec::new(),
+        };
+        render(markdown, &options).html()
+    }
+
+    #[test]
+    fn alert_containers_are_quoted() {
+        assert_eq!(
+            alert_containers(":::note\nSome text\n\nMore\n:::\nAfter\n"),
+            "> [!NOTE]\n> Some text\n>\n> More\n\nAfter\n"
+        );
+        assert_eq!(
+            alert_containers("::: Warning \r\nCareful\r\n:::\r\n"),
+            "> [!WARNING]\r\n> Careful\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn alert_containers_keep_lines() {
+        let markdown = "# Title\n\n:::tip\nA\n\nB\n:::\n\nEnd\n";
+        let quoted = alert_containers(markdown);
+        assert_eq!(quoted.lines().count(), markdown.lines().count());
+        assert_eq!(quoted.lines().nth(8), Some("End"));
+    }
+
+    #[test]
+    fn other_containers_are_left_alone() {
+        assert!(matches!(
+            alert_containers("No containers"),
+            Cow::Borrowed(_)
+        ));
+        for markdown in [
+            ":::details\nText\n:::\n",
+            "a ::: b\n",
+            ":::\nText\n",
+            "```\n:::note\nText\n:::\n```\n",
+            "~~~~\n```\n:::note\n~~~~\n",
+        ] {
+            assert_eq!(alert_containers(markdown), markdown);
+        }
+    }
+
+    #[test]
+    fn code_blocks_in_alert_containers() {
+        assert_eq!(
+            alert_containers(":::caution\n```\n:::\n```\n:::\n"),
+            "> [!CAUTION]\n> ```\n> :::\n> ```\n\n"
+        );
+    }
+
+    #[test]
+    fn alert_containers_do_not_nest() {
+        assert_eq!(
+            alert_containers(":::note\n:::tip\n:::\n:::\n"),
+            "> [!NOTE]\n> :::tip\n\n:::\n"
+        );
+    }
+
+    #[test]
+    fn alert_containers_render_as_alerts() {
+        let html = render_all(":::important\nRead this\n:::\n");
+        assert!(html.contains("class=\"markdown-alert markdown-alert-important\""));
+        assert!(html.contains("<p data-source-line=\"2\">Read this</p>"));
+    }
+
+    fn parse_headings(markdown: &str) -> Vec<Heading> {
+        let parser = Parser::new_ext(markdown, Extensions::all().options).into_offset_iter();
+        let events: Vec<_> = TextMergeWithOffset::new(parser).collect();
+        headings(&events)
+    }
+
+    fn heading_ids(markdown: &str) -> Vec<String> {
+        parse_headings(markdown)
+            .into_iter()
+            .map(|heading| heading.id)
+            .collect()
+    }
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slug("Hello, World!"), "hello-world");
+        assert_eq!(slug("  `code` and_more "), "code-and_more");
+        assert_eq!(slug("?!"), "section");
+        assert_eq!(heading_ids("# ...\n# ?\n"), ["section", "section-1"]);
+    }
+
+    #[test]
+    fn duplicate_slugs_are_numbered() {
+        assert_eq!(heading_ids("# x\n## x\n# X\n"), ["x", "x-1", "x-2"]);
+        assert_eq!(heading_ids("# x-1\n# x\n# x\n"), ["x-1", "x", "x-2"]);
+    }
+
+    #[test]
+    fn set_ids_take_precedence_over_slugs() {
+        assert_eq!(heading_ids("# A {#x}\n# x\n"), ["x", "x-1"]);
+        assert_eq!(heading_ids("# x\n# B {#x}\n"), ["x-1", "x"]);
+    }
+
+    #[test]
+    fn table_of_contents_nesting() {
+        let headings = parse_headings("## a\n### b\n# c\n## d\n");
+        assert_eq!(
+            table_of_contents(&headings, "toc"),
+            "<nav class=\"toc\">\n<ul>\n\
+             <li><a href=\"#a\">a</a><ul>\n\
+             <li><a href=\"#b\">b</a></li>\n</ul>\n</li>\n\
+             <li><a href=\"#c\">c</a><ul>\n\
+             <li><a href=\"#d\">d</a></li>\n</ul>\n</li>\n\
+             </ul>\n</nav>\n"
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use latex2mathml::{latex_to_mathml, DisplayStyle};
+
+use crate::markdown::escape;
+
+/// The elements latex2mathml writes.
+const MATHML_ELEMENTS: &[&str] = &[
+    "math",
+    "mfrac",
+    "mi",
+    "mmultiscripts",
+    "mn",
+    "mo",
+    "mover",
+    "mroot",
+    "mrow",
+    "mspace",
+    "msqrt",
+    "mstyle",
+    "msub",
+    "msubsup",
+    "msup",
+    "mtable",
+    "mtd",
+    "mtext",
+    "mtr",
+    "munder",
+    "munderover",
+    "semantics",
+];
+
+/// Convert LaTeX math to MathML, which browsers lay out by themselves, so
+/// that math needs no script and shows up in exported pages too. Math that
+/// cannot be converted is shown as written, with the error as a tooltip.
+pub fn render(latex: &str, display: bool) -> String {
+    let style = if display {
+        DisplayStyle::Block
+    } else {
+        DisplayStyle::Inline
+    };
+
+    match latex_to_mathml(latex.trim(), style) {
+        Ok(mathml) => escape_text(&mathml),
+        Err(e) => {
+            let title = escape(&format!("Could not render math: {e}"));
+            if display {
+                format!(
+                    "<pre class=\"mdr-math-error\" title=\"{title}\"><code>{}</code></pre>",
+                    escape(latex)
+                )
+            } else {
+                format!(
+                    "<code class=\"mdr-math-error\" title=\"{title}\">{}</code>",
+                    escape(latex)
+                )
+            }
+        }
+    }
+}
+
+/// latex2mathml writes the text of operators and `\text{}` as is, so escape
+/// every `<` that does not start one of its tags.
+fn escape_text(mathml: &str) -> String {
+    let mut escaped = String::with_capacity(mathml.len());
+
+    for (i, part) in mathml.split('<').enumerate() {
+        if i > 0 {
+            let name = part.strip_prefix('/').unwrap_or(part);
+            let name_end = name
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(name.len());
+            if MATHML_ELEMENTS.contains(&&name[..name_end]) {
+                escaped.push('<');
+            } else {
+                escaped.push_str("&lt;");
+            }
+        }
+        escaped.push_str(part);
+    }
+
+    escaped
+}
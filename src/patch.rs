@@ -0,0 +1,448 @@
+use serde::Serialize;
+
+/// The attribute marking elements with the source line they start on, as
+/// written in the blocks.
+const LINE_ATTRIBUTE: &str = " data-source-line=\"";
+
+/// A message telling the page how to update, sent as JSON over the websocket.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Update<'a> {
+    /// Replace the whole document with these blocks.
+    Full { blocks: &'a [String] },
+    /// Apply these changes, in order, to the blocks the page has.
+    Patch { ops: Vec<Op<'a>> },
+    /// Change the title of the page, set in the front matter.
+    Title { title: &'a str },
+    /// Load the page again, for a changed template.
+    Reload,
+    /// Load the custom stylesheets again.
+    ReloadCss,
+}
+
+/// A change to one top-level block. Indices refer to the blocks as they are
+/// once the previous changes are applied. `Shift` is for the `count` blocks
+/// from `index` that did not change but moved in the source: their source
+/// lines are shifted by `lines`.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Op<'a> {
+    Insert {
+        index: usize,
+        html: &'a str,
+    },
+    Replace {
+        index: usize,
+        html: &'a str,
+    },
+    Remove {
+        index: usize,
+    },
+    Shift {
+        index: usize,
+        count: usize,
+        lines: isize,
+    },
+}
+
+impl Update<'_> {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("updates are always serializable")
+    }
+}
+
+/// The changes turning `old` into `new`. Edits usually touch a few adjacent
+/// blocks, so everything between the unchanged start and end of the document
+/// is replaced. Blocks after an added or removed line only move, which is
+/// cheaper to send than the blocks themselves.
+pub fn diff<'a>(old: &[String], new: &'a [String]) -> Vec<Op<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| same(a, b)).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| same(a, b))
+        .count();
+
+    let removed = old.len() - suffix - prefix;
+    let inserted = &new[prefix..new.len() - suffix];
+    let replaced = removed.min(inserted.len());
+
+    let mut ops = shifts(0, &old[..prefix], &new[..prefix]);
+    for (index, html) in (prefix..).zip(inserted) {
+        if index < prefix + replaced {
+            ops.push(Op::Replace { index, html });
+        } else {
+            ops.push(Op::Insert { index, html });
+        }
+    }
+    for _ in replaced..removed {
+        ops.push(Op::Remove {
+            index: prefix + replaced,
+        });
+    }
+    let suffix_start = new.len() - suffix;
+    ops.extend(shifts(
+        suffix_start,
+        &old[old.len() - suffix..],
+        &new[suffix_start..],
+    ));
+
+    ops
+}
+
+/// Whether two blocks are the same, but maybe for the lines they start on.
+fn same(a: &str, b: &str) -> bool {
+    a == b
+        || match (first_line(a), first_line(b)) {
+            (Some(from), Some(to)) if from != to => {
+                shift_lines(a, to as isize - from as isize) == b
+            }
+            _ => false,
+        }
+}
+
+/// The changes moving the blocks of `old`, which are the same as `new` but
+/// for their lines, to the lines of `new`. Runs of blocks moved by as many
+/// lines are moved together. `start` is the index of the first block.
+fn shifts<'a>(start: usize, old: &[String], new: &[String]) -> Vec<Op<'a>> {
+    let mut ops: Vec<Op> = Vec::new();
+
+    for (index, (a, b)) in (start..).zip(old.iter().zip(new)) {
+        let (Some(from), Some(to)) = (first_line(a), first_line(b)) else {
+            continue;
+        };
+        let moved = to as isize - from as isize;
+        if moved == 0 {
+            continue;
+        }
+
+        match ops.last_mut() {
+            Some(Op::Shift {
+                index: first,
+                count,
+                lines,
+            }) if *first + *count == index && *lines == moved => *count += 1,
+            _ => ops.push(Op::Shift {
+                index,
+                count: 1,
+                lines: moved,
+            }),
+        }
+    }
+
+    ops
+}
+
+/// The source line a block starts on, as marked on its first element.
+fn first_line(block: &str) -> Option<usize> {
+    let start = block.find(LINE_ATTRIBUTE)? + LINE_ATTRIBUTE.len();
+    let end = start + block[start..].find('"')?;
+    block[start..end].parse().ok()
+}
+
+/// A block with its source lines shifted by `lines`, as the page does it.
+fn shift_lines(block: &str, lines: isize) -> String {
+    let mut moved = String::with_capacity(block.len());
+    let mut rest = block;
+    while let Some(start) = rest.find(LINE_ATTRIBUTE) {
+        let start = start + LINE_ATTRIBUTE.len();
+        moved.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        match rest[..end].parse::<usize>() {
+            Ok(n) => moved.push_str(&n.saturating_add_signed(lines).to_string()),
+            Err(_) => moved.push_str(&rest[..end]),
+        }
+        rest = &rest[end..];
+    }
+    moved.push_str(rest);
+
+    moved
+}
+
+/// The message bringing a page showing `old` up to date with `new`: the
+/// changed blocks, or the whole document when that is about as small. `None`
+/// when nothing changed.
+pub fn update(old: &[String], new: &[String]) -> Option<String> {
+    let ops = diff(old, new);
+    if ops.is_empty() {
+        return None;
+    }
+
+    let changed: usize = ops
+        .iter()
+        .map(|op| match op {
+            Op::Insert { html, .. } | Op::Replace { html, .. } => html.len(),
+            Op::Remove { .. } | Op::Shift { .. } => 0,
+        })
+        .sum();
+    let total: usize = new.iter().map(String::len).sum();
+
+    if changed < total {
+        Some(Update::Patch { ops }.to_json())
+    } else {
+        Some(Update::Full { blocks: new }.to_json())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(blocks: &[&str]) -> Vec<String> {
+        blocks.iter().map(|block| block.to_string()).collect()
+    }
+
+    /// Apply changes the way the page does.
+    fn apply(old: &[String], ops: &[Op]) -> Vec<String> {
+        let mut blocks = old.to_vec();
+        for op in ops {
+            match *op {
+                Op::Insert { index, html } => blocks.insert(index, html.to_string()),
+                Op::Replace { index, html } => blocks[index] = html.to_string(),
+                Op::Remove { index } => {
+                    blocks.remove(index);
+                }
+                Op::Shift {
+                    index,
+                    count,
+                    lines,
+                } => {
+                    for block in &mut blocks[index..index + count] {
+                        *block = shift_lines(block, lines);
+                    }
+                }
+            }
+        }
+        blocks
+    }
+
+    fn assert_diff(old: &[&str], new: &[&str], expected: &[Op]) {
+        let (old, new) = (blocks(old), blocks(new));
+        let ops = diff(&old, &new);
+        assert_eq!(ops, expected);
+        assert_eq!(apply(&old, &ops), new);
+    }
+
+    fn line(line: usize, text: &str) -> String {
+        format!("<p data-source-line=\"{line}\">{text}</p>\n")
+    }
+
+    #[test]
+    fn unchanged() {
+        assert_diff(&["a", "b"], &["a", "b"], &[]);
+        assert_eq!(update(&blocks(&["a", "b"]), &blocks(&["a", "b"])), None);
+    }
+
+    #[test]
+    fn insert() {
+        assert_diff(
+            &["a", "c"],
+            &["a", "b", "c"],
+            &[Op::Insert {
+                index: 1,
+                html: "b",
+            }],
+        );
+        assert_diff(
+            &["b"],
+            &["a", "b"],
+            &[Op::Insert {
+                index: 0,
+                html: "a",
+            }],
+        );
+        assert_diff(
+            &["a"],
+            &["a", "b"],
+            &[Op::Insert {
+                index: 1,
+                html: "b",
+            }],
+        );
+        assert_diff(
+            &[],
+            &["a", "b"],
+            &[
+                Op::Insert {
+                    index: 0,
+                    html: "a",
+                },
+                Op::Insert {
+                    index: 1,
+                    html: "b",
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn remove() {
+        assert_diff(
+            &["a", "b", "c", "d"],
+            &["a", "d"],
+            &[Op::Remove { index: 1 }, Op::Remove { index: 1 }],
+        );
+        assert_diff(&["a", "b"], &["b"], &[Op::Remove { index: 0 }]);
+        assert_diff(&["a", "b"], &["a"], &[Op::Remove { index: 1 }]);
+    }
+
+    #[test]
+    fn replace() {
+        assert_diff(
+            &["a", "b", "c"],
+            &["a", "x", "c"],
+            &[Op::Replace {
+                index: 1,
+                html: "x",
+            }],
+        );
+        assert_diff(
+            &["a", "b", "c", "d"],
+            &["a", "x", "d"],
+            &[
+                Op::Replace {
+                    index: 1,
+                    html: "x",
+                },
+                Op::Remove { index: 2 },
+            ],
+        );
+        assert_diff(
+            &["a", "b", "d"],
+            &["a", "x", "y", "d"],
+            &[
+                Op::Replace {
+                    index: 1,
+                    html: "x",
+                },
+                Op::Insert {
+                    index: 2,
+                    html: "y",
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn prefix_and_suffix_overlap() {
+        // The same blocks could be counted in both the unchanged start and end.
+        assert_diff(
+            &["a", "a"],
+            &["a", "a", "a"],
+            &[Op::Insert {
+                index: 2,
+                html: "a",
+            }],
+        );
+        assert_diff(&["a", "a", "a"], &["a", "a"], &[Op::Remove { index: 2 }]);
+        assert_diff(
+            &["a", "b", "a"],
+            &["a"],
+            &[Op::Remove { index: 1 }, Op::Remove { index: 1 }],
+        );
+        assert_diff(
+            &["a"],
+            &["a", "b", "a"],
+            &[
+                Op::Insert {
+                    index: 1,
+                    html: "b",
+                },
+                Op::Insert {
+                    index: 2,
+                    html: "a",
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn moved_blocks_are_shifted() {
+        let old = [line(1, "a"), line(3, "b"), line(5, "c")];
+        let new = [line(1, "x"), line(3, "a"), line(5, "b"), line(7, "c")];
+        let old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let new: Vec<&str> = new.iter().map(String::as_str).collect();
+        assert_diff(
+            &old,
+            &new,
+            &[
+                Op::Insert {
+                    index: 0,
+                    html: new[0],
+                },
+                Op::Shift {
+                    index: 1,
+                    count: 3,
+                    lines: 2,
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn shifts_are_grouped_by_distance() {
+        let old = [line(1, "a"), line(3, "b"), line(6, "c"), line(8, "d")];
+        let new = [line(1, "a"), line(2, "b"), line(5, "c"), line(6, "d")];
+        let old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let new: Vec<&str> = new.iter().map(String::as_str).collect();
+        assert_diff(
+            &old,
+            &new,
+            &[
+                Op::Shift {
+                    index: 1,
+                    count: 2,
+                    lines: -1,
+                },
+                Op::Shift {
+                    index: 3,
+                    count: 1,
+                    lines: -2,
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn shift_nested_lines() {
+        let list = "<ul data-source-line=\"4\">\n<li data-source-line=\"4\">a</li>\n<li data-source-line=\"5\">b</li>\n</ul>\n";
+        assert_eq!(
+            shift_lines(list, 10),
+            "<ul data-source-line=\"14\">\n<li data-source-line=\"14\">a</li>\n<li data-source-line=\"15\">b</li>\n</ul>\n"
+        );
+        assert_eq!(first_line(list), Some(4));
+        assert_eq!(first_line("<table>"), None);
+    }
+
+    #[test]
+    fn update_sends_changed_blocks() {
+        let old = blocks(&["<p>a</p>", "<p>b</p>", "<p>c</p>"]);
+        let new = blocks(&["<p>a</p>", "<p>x</p>", "<p>c</p>"]);
+        assert_eq!(
+            update(&old, &new).unwrap(),
+            r#"{"type":"patch","ops":[{"op":"replace","index":1,"html":"<p>x</p>"}]}"#
+        );
+
+        let new = blocks(&["<p>x</p>"]);
+        assert_eq!(
+            update(&old, &new).unwrap(),
+            r#"{"type":"full","blocks":["<p>x</p>"]}"#
+        );
+    }
+
+    #[test]
+    fn title() {
+        assert_eq!(
+            Update::Title {
+                title: "A \"quoted\" title"
+            }
+            .to_json(),
+            r#"{"type":"title","title":"A \"quoted\" title"}"#
+        );
+    }
+}
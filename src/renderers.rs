@@ -0,0 +1,149 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    io::{Read, Write},
+    process::{Command, Stdio},
+    sync::{Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::markdown::escape;
+
+/// How many rendered diagrams are kept before the cache is emptied.
+const CACHE_CAPACITY: usize = 256;
+
+/// How long a command gets to render a diagram before it is killed.
+const TIMEOUT_SEC: u64 = 10;
+
+/// How often a running command is checked for completion.
+const POLL_INTERVAL_MSEC: u64 = 10;
+
+/// A local command turning the code blocks of a language into SVG, like
+/// `dot -Tsvg` for Graphviz.
+#[derive(Clone, Debug)]
+pub struct Renderer {
+    pub lang: String,
+    command: Vec<String>,
+}
+
+impl Renderer {
+    /// Parse a renderer given as `lang=command`, the arguments of the command
+    /// being separated by spaces.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (lang, command) = spec
+            .split_once('=')
+            .ok_or_else(|| anyhow!("invalid renderer '{spec}' (expected lang=command)"))?;
+        let command: Vec<String> = command.split_whitespace().map(str::to_string).collect();
+
+        if lang.trim().is_empty() || command.is_empty() {
+            return Err(anyhow!("invalid renderer '{spec}' (expected lang=command)"));
+        }
+
+        Ok(Renderer {
+            lang: lang.trim().to_string(),
+            command,
+        })
+    }
+
+    /// The HTML of a code block: the SVG printed by the command given the code
+    /// on its input, or a box explaining why the command failed. Diagrams are
+    /// cached by their code, so that saving a document does not run the
+    /// command again for the diagrams that did not change.
+    pub fn render(&self, code: &str) -> String {
+        let mut hasher = DefaultHasher::new();
+        (&self.command, code).hash(&mut hasher);
+        let key = hasher.finish();
+
+        if let Some(html) = cache().lock().unwrap().get(&key) {
+            return html.clone();
+        }
+
+        match self.run(code) {
+            Ok(svg) => {
+                let html = format!("<div class=\"mdr-diagram\">{svg}</div>\n");
+                let mut cache = cache().lock().unwrap();
+                if cache.len() >= CACHE_CAPACITY {
+                    cache.clear();
+                }
+                cache.insert(key, html.clone());
+                html
+            }
+            // Failures are not cached, as they may be fixed outside of the
+            // document, by installing the command for instance.
+            Err(e) => format!(
+                "<div class=\"mdr-diagram-error\"><p>{}</p><pre><code>{}</code></pre></div>\n",
+                escape(&e.to_string()),
+                escape(code)
+            ),
+        }
+    }
+
+    fn run(&self, code: &str) -> Result<String> {
+        let program = &self.command[0];
+        let mut child = Command::new(program)
+            .args(&self.command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("Could not run {program}: {e}"))?;
+
+        // Written from another thread, so that a command writing a lot before
+        // reading all of its input cannot block us both.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = code.to_string();
+        thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+        // The output is read from other threads too, so that the command is
+        // not blocked on a full pipe while we wait for it to exit.
+        let stdout = read_all(child.stdout.take().expect("stdout is piped"));
+        let stderr = read_all(child.stderr.take().expect("stderr is piped"));
+
+        let deadline = Instant::now() + Duration::from_secs(TIMEOUT_SEC);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow!("{program} timed out after {TIMEOUT_SEC} seconds"));
+            }
+            thread::sleep(Duration::from_millis(POLL_INTERVAL_MSEC));
+        };
+
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = stderr.join().unwrap_or_default();
+        if !status.success() {
+            let stderr = String::from_utf8_lossy(&stderr);
+            return Err(match stderr.trim() {
+                "" => anyhow!("{program} failed ({status})"),
+                stderr => anyhow!("{program} failed ({status}): {stderr}"),
+            });
+        }
+
+        // Drop the XML declaration and doctype that come before the SVG.
+        let output = String::from_utf8_lossy(&stdout);
+        match output.find("<svg") {
+            Some(start) => Ok(output[start..].trim_end().to_string()),
+            None => Err(anyhow!("{program} did not print an SVG image")),
+        }
+    }
+}
+
+/// Read everything from a pipe on another thread.
+fn read_all(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = pipe.read_to_end(&mut output);
+        output
+    })
+}
+
+fn cache() -> &'static Mutex<HashMap<u64, String>> {
+    static CACHE: OnceLock<Mutex<HashMap<u64, String>>> = OnceLock::new();
+    CACHE.get_or_init(Mutex::default)
+}
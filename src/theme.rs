@@ -0,0 +1,97 @@
+use anyhow::{anyhow, Result};
+
+use crate::highlight;
+
+/// A color theme of the preview, and the highlighting theme that goes with it.
+pub struct Theme {
+    pub name: &'static str,
+    pub highlight_theme: &'static str,
+}
+
+pub const THEMES: &[Theme] = &[
+    Theme {
+        name: "light",
+        highlight_theme: "InspiredGitHub",
+    },
+    Theme {
+        name: "dark",
+        highlight_theme: "base16-ocean.dark",
+    },
+    Theme {
+        name: "solarized-light",
+        highlight_theme: "Solarized (light)",
+    },
+    Theme {
+        name: "solarized-dark",
+        highlight_theme: "Solarized (dark)",
+    },
+];
+
+/// Follows the light or dark preference of the browser.
+pub const AUTO: &str = "auto";
+
+pub const DEFAULT_THEME: &str = "light";
+
+/// The names accepted by `--theme`.
+pub fn names() -> Vec<&'static str> {
+    let mut names = vec![AUTO];
+    names.extend(THEMES.iter().map(|theme| theme.name));
+    names
+}
+
+pub fn validate(name: &str) -> Result<()> {
+    if names().contains(&name) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "unknown theme '{name}' (expected one of: {})",
+            names().join(", ")
+        ))
+    }
+}
+
+/// The highlighting stylesheet of every theme, each scoped to the pages whose
+/// `data-theme` selects it, so that switching themes needs no reload. When
+/// `highlight_theme` is given, it is used whatever the page theme.
+pub fn highlight_css(highlight_theme: Option<&str>) -> Result<String> {
+    let mut css = highlight::LINE_NUMBERS_CSS.to_string();
+
+    for theme in THEMES {
+        let theme_css = highlight::css(highlight_theme.unwrap_or(theme.highlight_theme))?;
+        css.push_str(&scope(&theme_css, theme.name));
+    }
+
+    // `auto` uses the highlighting of `light`, or of `dark` in dark mode.
+    let [light, dark] = ["light", "dark"].map(|name| {
+        THEMES
+            .iter()
+            .find(|theme| theme.name == name)
+            .map_or("", |theme| theme.highlight_theme)
+    });
+    let light = highlight::css(highlight_theme.unwrap_or(light))?;
+    let dark = highlight::css(highlight_theme.unwrap_or(dark))?;
+    css.push_str(&scope(&light, AUTO));
+    css.push_str("@media (prefers-color-scheme: dark) {\n");
+    css.push_str(&scope(&dark, AUTO));
+    css.push_str("}\n");
+
+    Ok(css)
+}
+
+/// Prefix every selector of a stylesheet with the attribute selector of a theme.
+fn scope(css: &str, theme: &str) -> String {
+    let prefix = format!("html[data-theme=\"{theme}\"]");
+
+    css.lines()
+        .map(|line| match line.strip_suffix('{') {
+            Some(selectors) if !line.trim_start().starts_with('@') => {
+                let selectors: Vec<String> = selectors
+                    .split(',')
+                    .map(|selector| format!("{prefix} {}", selector.trim()))
+                    .collect();
+                format!("{} {{\n", selectors.join(", "))
+            }
+            _ => format!("{line}\n"),
+        })
+        .collect()
+}
@@ -0,0 +1,208 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use notify::{
+    event::{ModifyKind, RenameMode},
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    time::{timeout, Duration},
+};
+
+/// How long the files must stay quiet before a burst of events is reported.
+const DEBOUNCE_WATCH_MSEC: u64 = 50;
+
+/// A change to one of the watched files.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// The file was written, created or removed.
+    Modified(PathBuf),
+    /// The file was renamed within its directory, and no other file took its
+    /// place.
+    Renamed { from: PathBuf, to: PathBuf },
+}
+
+/// The OS watcher shared by every `FileWatcher`, along with the directories it
+/// watches. Each OS watcher takes an inotify instance on Linux, and users only
+/// get 128 of them by default, so one watcher per open document would not do.
+struct SharedWatcher {
+    watcher: RecommendedWatcher,
+    dirs: HashSet<PathBuf>,
+}
+
+static WATCHER: Mutex<Option<SharedWatcher>> = Mutex::new(None);
+
+/// The file watchers that the events of the shared watcher are dispatched to.
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+struct Subscriber {
+    files: Arc<Mutex<Vec<PathBuf>>>,
+    events_tx: UnboundedSender<Event>,
+}
+
+/// Send an event to the file watchers watching one of its paths, forgetting
+/// those that were dropped.
+fn dispatch(event: notify::Result<Event>) {
+    let event = match event {
+        Ok(event) => event,
+        Err(e) => {
+            eprintln!("Error: file watcher: {e}");
+            return;
+        }
+    };
+
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    subscribers.retain(|subscriber| !subscriber.events_tx.is_closed());
+    for subscriber in subscribers.iter() {
+        let files = subscriber.files.lock().unwrap();
+        if event.paths.iter().any(|path| files.contains(path)) {
+            let _ = subscriber.events_tx.send(event.clone());
+        }
+    }
+}
+
+/// Watch a directory with the shared watcher, starting it on first use.
+fn watch_dir(dir: &Path) -> Result<()> {
+    let mut shared = WATCHER.lock().unwrap();
+    let shared = match &mut *shared {
+        Some(shared) => shared,
+        None => shared.insert(SharedWatcher {
+            watcher: notify::recommended_watcher(dispatch)?,
+            dirs: HashSet::new(),
+        }),
+    };
+
+    if !shared.dirs.contains(dir) {
+        shared.watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        shared.dirs.insert(dir.to_path_buf());
+    }
+
+    Ok(())
+}
+
+/// Watches files through OS notifications (inotify on Linux).
+///
+/// The parent directories are watched rather than the files themselves, so
+/// editors that save atomically by renaming a temporary file over the original,
+/// or by deleting and recreating it, keep being tracked.
+pub struct FileWatcher {
+    events_rx: UnboundedReceiver<Event>,
+    files: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl FileWatcher {
+    pub fn new(files: &[PathBuf]) -> Result<Self> {
+        let (events_tx, events_rx) = unbounded_channel();
+
+        let mut file_watcher = FileWatcher {
+            events_rx,
+            files: Arc::default(),
+        };
+        SUBSCRIBERS.lock().unwrap().push(Subscriber {
+            files: file_watcher.files.clone(),
+            events_tx,
+        });
+
+        for file in files {
+            file_watcher.watch(file)?;
+        }
+
+        Ok(file_watcher)
+    }
+
+    /// Start watching another file.
+    pub fn watch(&mut self, file: &Path) -> Result<()> {
+        let file = absolute(file)?;
+        let dir = file
+            .parent()
+            .ok_or_else(|| anyhow!("could not watch {}", file.display()))?;
+
+        // Not holding the lock on the files, which events are dispatched by.
+        watch_dir(dir)?;
+
+        let mut files = self.files.lock().unwrap();
+        if !files.contains(&file) {
+            files.push(file);
+        }
+
+        Ok(())
+    }
+
+    /// Keep watching a renamed file under its new name.
+    pub fn follow(&mut self, from: &Path, to: &Path) -> Result<()> {
+        self.files.lock().unwrap().retain(|file| file != from);
+        self.watch(to)
+    }
+
+    /// Wait until one of the watched files changes, then until events stop
+    /// coming in for a moment, and return what changed.
+    pub async fn changed(&mut self) -> Result<Vec<Change>> {
+        let mut changed = Vec::new();
+
+        while changed.is_empty() {
+            let event = self
+                .events_rx
+                .recv()
+                .await
+                .ok_or_else(|| anyhow!("file watcher stopped"))?;
+            self.collect(event, &mut changed);
+        }
+
+        let debounce = Duration::from_millis(DEBOUNCE_WATCH_MSEC);
+        while let Ok(event) = timeout(debounce, self.events_rx.recv()).await {
+            let event = event.ok_or_else(|| anyhow!("file watcher stopped"))?;
+            self.collect(event, &mut changed);
+        }
+
+        // Editors like vim save by renaming the file to a backup and writing a
+        // new one in its place: that file is the one to keep watching.
+        changed.retain(|change| match change {
+            Change::Renamed { from, .. } => !from.exists(),
+            Change::Modified(_) => true,
+        });
+
+        Ok(changed)
+    }
+
+    fn collect(&self, event: Event, changed: &mut Vec<Change>) {
+        if event.kind.is_access() {
+            return;
+        }
+
+        let files = self.files.lock().unwrap();
+
+        if let (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to]) =
+            (event.kind, event.paths.as_slice())
+        {
+            if files.contains(from) {
+                changed.push(Change::Renamed {
+                    from: from.clone(),
+                    to: to.clone(),
+                });
+            }
+        }
+
+        for path in event.paths {
+            if files.contains(&path) {
+                let change = Change::Modified(path);
+                if !changed.contains(&change) {
+                    changed.push(change);
+                }
+            }
+        }
+    }
+}
+
+/// Make a path absolute without requiring it to exist, as the paths reported
+/// by notify are.
+pub fn absolute(path: &Path) -> Result<PathBuf> {
+    match path.canonicalize() {
+        Ok(path) => Ok(path),
+        Err(_) => Ok(std::path::absolute(path)?),
+    }
+}